@@ -0,0 +1,8 @@
+use std::io;
+
+fn main() {
+    if let Err(e) = spirv_compiler::cli::run(std::env::args().skip(1), io::stdin(), io::stdout()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
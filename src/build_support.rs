@@ -0,0 +1,59 @@
+//! Helpers for compiling shaders from a `build.rs` script.
+
+use crate::{words_to_bytes, Compiler, CompilerError, ShaderKind};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// A shader compiled by `compile_and_emit`, written to `<name>.spv`.
+#[derive(Debug, Clone)]
+pub struct ShaderEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: ShaderKind,
+}
+
+impl ShaderEntry {
+    pub fn new<T: AsRef<Path>>(name: &str, path: T, kind: ShaderKind) -> Self {
+        Self {
+            name: String::from(name),
+            path: path.as_ref().to_path_buf(),
+            kind,
+        }
+    }
+}
+
+/// Compiles every entry with a default compiler, see `compile_and_emit_with`.
+pub fn compile_and_emit(out_dir: &Path, entries: &[ShaderEntry]) -> Result<(), CompilerError> {
+    let mut compiler = Compiler::new()?;
+    compile_and_emit_with(&mut compiler, out_dir, entries)
+}
+
+/// Compiles every entry into `out_dir/<name>.spv` and tells cargo to rerun the build script
+/// when the shader or any of the files it includes changes.
+pub fn compile_and_emit_with(
+    compiler: &mut Compiler,
+    out_dir: &Path,
+    entries: &[ShaderEntry],
+) -> Result<(), CompilerError> {
+    for entry in entries {
+        println!("cargo:rerun-if-changed={}", entry.path.display());
+
+        let binary = compiler.compile_from_file(&entry.path, entry.kind, false);
+        // Includes are resolved even when compilation fails, emit them so fixing one reruns us
+        for include in compiler.last_includes() {
+            println!("cargo:rerun-if-changed={}", include.display());
+        }
+
+        if let Err(CompilerError::Log(e)) = binary.as_ref() {
+            e.emit_cargo_warnings();
+        }
+
+        let output = out_dir.join(format!("{}.spv", entry.name));
+        if let Err(e) = std::fs::write(output, words_to_bytes(&binary?)) {
+            return Err(CompilerError::WriteError(Arc::new(e)));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use crate::spirv;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    binary: Arc<[u32]>,
+    last_used: u64,
+}
+
+/// In-memory cache of compiled binaries by source path.
+///
+/// When a capacity is set, the least recently used binary is evicted once it is exceeded.
+/// Binaries are shared, so a cache hit doesn't copy the binary.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryCache {
+    entries: HashMap<PathBuf, CacheEntry>,
+    capacity: Option<usize>,
+    clock: u64,
+}
+
+impl MemoryCache {
+    pub(crate) fn new(capacity: Option<usize>) -> Self {
+        MemoryCache {
+            entries: HashMap::new(),
+            capacity,
+            clock: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, path: &Path) -> Option<&Arc<[u32]>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(path).map(|entry| {
+            entry.last_used = clock;
+            &entry.binary
+        })
+    }
+
+    pub(crate) fn insert<B: Into<Arc<[u32]>>>(&mut self, path: PathBuf, binary: B) {
+        self.clock += 1;
+        self.entries.insert(
+            path,
+            CacheEntry {
+                binary: binary.into(),
+                last_used: self.clock,
+            },
+        );
+
+        if let Some(capacity) = self.capacity {
+            while self.entries.len() > capacity {
+                let oldest = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(path, _)| path.clone());
+                match oldest {
+                    Some(oldest) => self.entries.remove(&oldest),
+                    None => break,
+                };
+            }
+        }
+    }
+
+    /// Unlike `get`, this doesn't count as a use of the binary.
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    pub(crate) fn remove(&mut self, path: &Path) -> bool {
+        self.entries.remove(path).is_some()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Serializes every entry with a UTF-8 path, sorted by path, after a header holding
+    /// `fingerprint` and `saved_at` in nanoseconds since the Unix epoch.
+    pub(crate) fn to_bytes(&self, fingerprint: u64, saved_at: u64) -> Vec<u8> {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .filter_map(|(path, entry)| path.to_str().map(|path| (path, &entry.binary)))
+            .collect();
+        entries.sort();
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(SAVED_CACHE_MAGIC);
+        bytes.extend_from_slice(&fingerprint.to_le_bytes());
+        bytes.extend_from_slice(&saved_at.to_le_bytes());
+        bytes.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+        for (path, binary) in entries {
+            bytes.extend_from_slice(&(path.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(path.as_bytes());
+            bytes.extend_from_slice(&(binary.len() as u64).to_le_bytes());
+            bytes.extend(binary.iter().flat_map(|word| word.to_le_bytes()));
+        }
+        bytes
+    }
+}
+
+const SAVED_CACHE_MAGIC: &[u8] = b"SPVCACHE";
+
+/// The contents of a cache written by `MemoryCache::to_bytes`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SavedCache {
+    pub(crate) fingerprint: u64,
+    pub(crate) saved_at: u64,
+    pub(crate) entries: Vec<(PathBuf, Vec<u32>)>,
+}
+
+impl SavedCache {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<SavedCache, String> {
+        let mut reader = ByteReader { bytes };
+        if reader.take(SAVED_CACHE_MAGIC.len())? != SAVED_CACHE_MAGIC {
+            return Err(String::from("not a saved shader cache"));
+        }
+
+        let fingerprint = reader.u64()?;
+        let saved_at = reader.u64()?;
+        let count = reader.u64()?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let path_len = reader.u64()? as usize;
+            let path = std::str::from_utf8(reader.take(path_len)?)
+                .map_err(|_| String::from("cache entry path is not valid UTF-8"))?;
+            let word_count = reader.u64()? as usize;
+            let binary = reader
+                .take(word_count.saturating_mul(4))?
+                .chunks_exact(4)
+                .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+                .collect();
+            entries.push((PathBuf::from(path), binary));
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(String::from("unexpected data after the last cache entry"));
+        }
+        Ok(SavedCache {
+            fingerprint,
+            saved_at,
+            entries,
+        })
+    }
+}
+
+/// Tag at the start of cache files written next to sources, `SPVC` in little-endian.
+const SIDECAR_TAG: u32 = u32::from_le_bytes(*b"SPVC");
+const SIDECAR_VERSION: u32 = 1;
+/// Number of words before the binary in a cache file: the tag, the version, the cache key
+/// and the CRC-32 of the binary.
+pub(crate) const SIDECAR_HEADER_WORDS: usize = 5;
+
+/// Prepends the cache file header to `binary`.
+pub(crate) fn to_sidecar(cache_key: u64, binary: &[u32]) -> Vec<u32> {
+    let mut words = Vec::with_capacity(SIDECAR_HEADER_WORDS + binary.len());
+    words.extend_from_slice(&[
+        SIDECAR_TAG,
+        SIDECAR_VERSION,
+        cache_key as u32,
+        (cache_key >> 32) as u32,
+        crc32(binary),
+    ]);
+    words.extend_from_slice(binary);
+    words
+}
+
+/// Returns whether the header at the start of `words` belongs to a cache file with
+/// `cache_key`. Only the header is checked, not the binary.
+pub(crate) fn sidecar_header_matches(words: &[u32], cache_key: u64) -> bool {
+    words.len() >= SIDECAR_HEADER_WORDS
+        && words[..4]
+            == [
+                SIDECAR_TAG,
+                SIDECAR_VERSION,
+                cache_key as u32,
+                (cache_key >> 32) as u32,
+            ]
+}
+
+/// Returns the binary of a cache file stored with `cache_key`, or `None` if it is stale,
+/// written by another version, or corrupt.
+pub(crate) fn from_sidecar(words: &[u32], cache_key: u64) -> Option<&[u32]> {
+    if !sidecar_header_matches(words, cache_key) {
+        return None;
+    }
+    let binary = &words[SIDECAR_HEADER_WORDS..];
+    // A binary written with the other endianness fails the checksum as well
+    if binary.first() != Some(&spirv::MAGIC) || crc32(binary) != words[4] {
+        return None;
+    }
+    Some(binary)
+}
+
+/// CRC-32 (IEEE) of the little-endian bytes of `words`.
+fn crc32(words: &[u32]) -> u32 {
+    crc32_bytes(words.iter().flat_map(|word| word.to_le_bytes()))
+}
+
+fn crc32_bytes<I: IntoIterator<Item = u8>>(bytes: I) -> u32 {
+    !bytes.into_iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err(String::from("saved shader cache is truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let bytes = self.take(8)?;
+        let mut word = [0; 8];
+        word.copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(word))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut cache = MemoryCache::new(Some(2));
+        cache.insert(PathBuf::from("a"), vec![1]);
+        cache.insert(PathBuf::from("b"), vec![2]);
+        // Touch a, so b is the least recently used
+        assert!(cache.get(Path::new("a")).is_some());
+        cache.insert(PathBuf::from("c"), vec![3]);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(Path::new("a")).is_some());
+        assert!(cache.get(Path::new("b")).is_none());
+        assert!(cache.get(Path::new("c")).is_some());
+    }
+
+    #[test]
+    fn test_saved_cache() {
+        let mut cache = MemoryCache::new(None);
+        cache.insert(PathBuf::from("b.frag"), vec![3]);
+        cache.insert(PathBuf::from("a.vert"), vec![1, 2]);
+
+        let bytes = cache.to_bytes(42, 7);
+        assert_eq!(
+            SavedCache::from_bytes(&bytes),
+            Ok(SavedCache {
+                fingerprint: 42,
+                saved_at: 7,
+                entries: vec![
+                    (PathBuf::from("a.vert"), vec![1, 2]),
+                    (PathBuf::from("b.frag"), vec![3]),
+                ],
+            })
+        );
+        assert!(SavedCache::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(SavedCache::from_bytes(b"not a cache").is_err());
+    }
+
+    #[test]
+    fn test_sidecar() {
+        // The standard check value, the CRC of "123456789"
+        assert_eq!(crc32_bytes(b"123456789".iter().copied()), 0xcbf4_3926);
+
+        let binary = [spirv::MAGIC, 0x0001_0000, 0, 1, 0];
+        let mut words = to_sidecar(42, &binary);
+        assert_eq!(from_sidecar(&words, 42), Some(&binary[..]));
+        assert_eq!(from_sidecar(&words, 43), None);
+        assert!(sidecar_header_matches(&words, 42));
+
+        words[SIDECAR_HEADER_WORDS + 3] ^= 1;
+        assert_eq!(from_sidecar(&words, 42), None);
+        assert_eq!(from_sidecar(&words[..SIDECAR_HEADER_WORDS - 1], 42), None);
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let mut cache = MemoryCache::new(None);
+        for i in 0..100 {
+            cache.insert(PathBuf::from(i.to_string()), vec![i]);
+        }
+        assert_eq!(cache.len(), 100);
+    }
+}
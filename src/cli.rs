@@ -0,0 +1,146 @@
+//! A command line front end that compiles a shader read from stdin to SPIR-V on stdout.
+//!
+//! ```text
+//! spirv-compiler --kind frag [--target-env vulkan1.2] [-D MACRO[=VALUE]]... [-I DIR]...
+//! ```
+
+use crate::{
+    extension_to_kind, settings::TARGET_ENVS, words_to_bytes, CompilerBuilder, CompilerError,
+    ShaderKind,
+};
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{Read, Write},
+    sync::Arc,
+};
+
+#[derive(Debug, Clone)]
+pub enum CliError {
+    InvalidArgument(String),
+    Compiler(CompilerError),
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::InvalidArgument(e) => write!(f, "Error: invalid argument: {}", e),
+            CliError::Compiler(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for CliError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CliError::Compiler(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<CompilerError> for CliError {
+    fn from(val: CompilerError) -> Self {
+        CliError::Compiler(val)
+    }
+}
+
+/// Compiles the shader read from `input` with the options in `args`, which exclude the
+/// program name, and writes the binary to `output`.
+pub fn run<I, R, W>(args: I, input: R, mut output: W) -> Result<(), CliError>
+where
+    I: IntoIterator<Item = String>,
+    R: Read,
+    W: Write,
+{
+    let mut builder = CompilerBuilder::new();
+    let mut kind = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str, inline: &str| {
+            if inline.is_empty() {
+                args.next()
+                    .ok_or_else(|| CliError::InvalidArgument(format!("{} requires a value", flag)))
+            } else {
+                Ok(String::from(inline))
+            }
+        };
+
+        if arg == "--kind" {
+            let name = value("--kind", "")?;
+            kind = Some(extension_to_kind(&name).ok_or_else(|| {
+                CliError::InvalidArgument(format!("unknown shader kind: {}", name))
+            })?);
+        } else if arg == "--target-env" {
+            let name = value("--target-env", "")?;
+            let (_, env, version) = TARGET_ENVS
+                .iter()
+                .find(|(env, _, _)| *env == name)
+                .ok_or_else(|| {
+                    CliError::InvalidArgument(format!("unknown target environment: {}", name))
+                })?;
+            builder = builder.with_target_env(*env, *version as u32);
+        } else if let Some(inline) = arg.strip_prefix("-D") {
+            let definition = value("-D", inline)?;
+            // shaderc panics on macros with a NUL in them instead of reporting an error
+            if definition.contains('\0') {
+                return Err(CliError::InvalidArgument(format!(
+                    "-D{:?} contains a NUL character",
+                    definition
+                )));
+            }
+            builder = match definition.split_once('=') {
+                Some((name, value)) => builder.with_macro(name, Some(value)),
+                None => builder.with_macro(definition.as_str(), None),
+            };
+        } else if let Some(inline) = arg.strip_prefix("-I") {
+            builder = builder.with_include_dir(value("-I", inline)?);
+        } else {
+            return Err(CliError::InvalidArgument(arg));
+        }
+    }
+
+    let kind: ShaderKind =
+        kind.ok_or_else(|| CliError::InvalidArgument(String::from("--kind is required")))?;
+    let binary = builder.build()?.compile_from_reader(input, kind, "stdin")?;
+    output
+        .write_all(&words_to_bytes(&binary))
+        .map_err(|e| CompilerError::WriteError(Arc::new(e)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::*;
+    use std::fs::File;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[test]
+    fn test_run() {
+        let input = File::open("test-spirv/test-macro.vert").unwrap();
+        let mut output = Vec::new();
+        let result = run(
+            args(&["--kind", "vert", "-DMY_MACRO=1", "-I", "test-spirv"]),
+            input,
+            &mut output,
+        );
+        assert!(result.is_ok());
+        assert_eq!(&output[..4], &0x0723_0203u32.to_le_bytes());
+
+        let input = File::open("test-spirv/test-macro.vert").unwrap();
+        let result = run(args(&["--kind", "vertex"]), input, &mut output);
+        assert!(matches!(result, Err(CliError::InvalidArgument(_))));
+
+        let input = File::open("test-spirv/test-macro.vert").unwrap();
+        let result = run(
+            args(&["--kind", "vert", "-D", "MY\0MACRO"]),
+            input,
+            &mut output,
+        );
+        assert!(matches!(result, Err(CliError::InvalidArgument(_))));
+    }
+}
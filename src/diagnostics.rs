@@ -0,0 +1,173 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::PathBuf,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A single message reported by shaderc.
+///
+/// `line` is 0 when shaderc did not attribute the message to a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: Option<PathBuf>,
+    pub line: u32,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = self.file.as_ref() {
+            write!(f, "{}:", file.display())?;
+        }
+        if self.line > 0 {
+            write!(f, "{}:", self.line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, "{}:", column)?;
+        }
+        write!(f, " {}: {}", self.severity, self.message)
+    }
+}
+
+impl Diagnostic {
+    /// Formats the diagnostic as a build script instruction that makes cargo show it.
+    pub fn to_cargo_warning(&self) -> String {
+        format!("cargo:warning={}", self)
+    }
+}
+
+/// Parses shaderc messages of the form `file:line: error: message` into diagnostics.
+///
+/// Lines that don't look like a diagnostic, such as shaderc's error count summary, are skipped.
+pub fn parse_diagnostics(messages: &str) -> Vec<Diagnostic> {
+    messages.lines().filter_map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    // shaderc::Error's Display implementation prefixes a single error with this
+    let line = line.trim().trim_start_matches("compilation error: ");
+
+    let (location, severity, message) = [
+        (": error: ", Severity::Error),
+        (": warning: ", Severity::Warning),
+    ]
+    .iter()
+    .filter_map(|(pattern, severity)| {
+        line.find(pattern)
+            .map(|index| (&line[..index], *severity, &line[index + pattern.len()..]))
+    })
+    .min_by_key(|(location, _, _)| location.len())?;
+
+    // The location is `file`, `file:line` or `file:line:column`, where `file` may contain colons
+    let (file, line, column) = match split_number(location) {
+        None => (location, 0, None),
+        Some((rest, last)) => match split_number(rest) {
+            Some((file, line)) => (file, line, Some(last)),
+            None => (rest, last, None),
+        },
+    };
+
+    Some(Diagnostic {
+        file: if file.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(file))
+        },
+        line,
+        column,
+        severity,
+        message: String::from(message.trim()),
+    })
+}
+
+/// Splits a trailing `:number` off of `location`.
+fn split_number(location: &str) -> Option<(&str, u32)> {
+    let index = location.rfind(':')?;
+    let number = location[index + 1..].trim().parse().ok()?;
+    Some((&location[..index], number))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostics::*;
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let diagnostics = parse_diagnostics(
+            "2 compilation errors:\n\
+             test-spirv/a.vert:12: error: 'V' : undeclared identifier\n\
+             C:\\shaders\\b.frag:3:7: warning: unused variable\n\
+             C:\\shaders\\c.frag:4: error: syntax error\n\
+             memory: error: '#version' : bad profile name\n\
+             2 errors generated.",
+        );
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic {
+                    file: Some(PathBuf::from("test-spirv/a.vert")),
+                    line: 12,
+                    column: None,
+                    severity: Severity::Error,
+                    message: String::from("'V' : undeclared identifier"),
+                },
+                Diagnostic {
+                    file: Some(PathBuf::from("C:\\shaders\\b.frag")),
+                    line: 3,
+                    column: Some(7),
+                    severity: Severity::Warning,
+                    message: String::from("unused variable"),
+                },
+                Diagnostic {
+                    file: Some(PathBuf::from("C:\\shaders\\c.frag")),
+                    line: 4,
+                    column: None,
+                    severity: Severity::Error,
+                    message: String::from("syntax error"),
+                },
+                Diagnostic {
+                    file: Some(PathBuf::from("memory")),
+                    line: 0,
+                    column: None,
+                    severity: Severity::Error,
+                    message: String::from("'#version' : bad profile name"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cargo_warning() {
+        let diagnostics = parse_diagnostics("a.vert:12:3: error: 'V' : undeclared identifier");
+        assert_eq!(
+            diagnostics[0].to_cargo_warning(),
+            "cargo:warning=a.vert:12:3: error: 'V' : undeclared identifier"
+        );
+    }
+
+    #[test]
+    fn test_parse_single_error() {
+        let diagnostics =
+            parse_diagnostics("compilation error: memory:9: error: 'V' : undeclared identifier");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file, Some(PathBuf::from("memory")));
+        assert_eq!(diagnostics[0].line, 9);
+    }
+}
@@ -1,24 +1,326 @@
+#[cfg(feature = "build-support")]
+pub mod build_support;
+#[cfg(feature = "fs")]
+mod cache;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod diagnostics;
+mod reflect;
+mod settings;
+mod spirv;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+#[cfg(feature = "fs")]
+use cache::{MemoryCache, SavedCache};
+pub use diagnostics::{parse_diagnostics, Diagnostic, Severity};
+pub use reflect::{reflect, BindingInfo, DescriptorKind, PushConstantRange, Reflection};
+pub use settings::CompileSettings;
 pub use shaderc::{
-    GlslProfile, Limit, OptimizationLevel, ResourceKind, ShaderKind, SourceLanguage, SpirvVersion,
-    TargetEnv,
+    GlslProfile, IncludeType, Limit, OptimizationLevel, ResolvedInclude, ResourceKind, ShaderKind,
+    SourceLanguage, SpirvVersion, TargetEnv,
 };
+pub use spirv::{spirv_version_of, strip_debug};
 use std::{
-    cmp::Ordering,
+    borrow::Cow,
     collections::HashMap,
     error::Error,
-    ffi::OsString,
     fmt::{Debug, Display},
-    fs::File,
-    io::{Read, Write},
+    io::{self, Read},
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+#[cfg(feature = "fs")]
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    ffi::OsString,
+    fs::File,
+    io::Write,
+    iter::FromIterator,
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Weak,
+    },
+    time::SystemTime,
 };
 
+/// File extensions recognized when inferring the shader kind of a file, see `extension_to_kind`.
+pub const SHADER_EXTENSIONS: &[(&str, ShaderKind)] = &[
+    ("vert", ShaderKind::Vertex),
+    ("frag", ShaderKind::Fragment),
+    ("comp", ShaderKind::Compute),
+    ("geom", ShaderKind::Geometry),
+    ("tesc", ShaderKind::TessControl),
+    ("tese", ShaderKind::TessEvaluation),
+    ("rgen", ShaderKind::RayGeneration),
+    ("rahit", ShaderKind::AnyHit),
+    ("rchit", ShaderKind::ClosestHit),
+    ("rmiss", ShaderKind::Miss),
+    ("rint", ShaderKind::Intersection),
+    ("rcall", ShaderKind::Callable),
+    ("task", ShaderKind::Task),
+    ("mesh", ShaderKind::Mesh),
+];
+
+/// Returns the shader kind of files with extension `ext`, such as `"frag"`, ignoring case.
+pub fn extension_to_kind(ext: &str) -> Option<ShaderKind> {
+    SHADER_EXTENSIONS
+        .iter()
+        .find(|(e, _)| e.eq_ignore_ascii_case(ext))
+        .map(|(_, kind)| *kind)
+}
+
+/// Returns the file extension of shaders of `kind`, the inverse of `extension_to_kind`.
+/// `Default*` kinds map to the extension of their stage, kinds without a stage to `"glsl"`
+/// or, for assembly, `"spvasm"`.
+pub fn default_extension(kind: ShaderKind) -> &'static str {
+    let kind = match kind {
+        ShaderKind::DefaultVertex => ShaderKind::Vertex,
+        ShaderKind::DefaultFragment => ShaderKind::Fragment,
+        ShaderKind::DefaultCompute => ShaderKind::Compute,
+        ShaderKind::DefaultGeometry => ShaderKind::Geometry,
+        ShaderKind::DefaultTessControl => ShaderKind::TessControl,
+        ShaderKind::DefaultTessEvaluation => ShaderKind::TessEvaluation,
+        ShaderKind::DefaultRayGeneration => ShaderKind::RayGeneration,
+        ShaderKind::DefaultAnyHit => ShaderKind::AnyHit,
+        ShaderKind::DefaultClosestHit => ShaderKind::ClosestHit,
+        ShaderKind::DefaultMiss => ShaderKind::Miss,
+        ShaderKind::DefaultIntersection => ShaderKind::Intersection,
+        ShaderKind::DefaultCallable => ShaderKind::Callable,
+        ShaderKind::DefaultTask => ShaderKind::Task,
+        ShaderKind::DefaultMesh => ShaderKind::Mesh,
+        ShaderKind::SpirvAssembly => return "spvasm",
+        kind => kind,
+    };
+    SHADER_EXTENSIONS
+        .iter()
+        .find(|(_, k)| *k == kind)
+        .map_or("glsl", |(ext, _)| *ext)
+}
+
+/// Like `extension_to_kind`, but returns `ShaderKind::InferFromSource` for extensions that
+/// don't name a stage, leaving the stage to a `#pragma shader_stage` in the source.
+pub fn extension_to_kind_or_infer(ext: &str) -> ShaderKind {
+    extension_to_kind(ext).unwrap_or(ShaderKind::InferFromSource)
+}
+
+#[cfg(feature = "fs")]
+fn shader_kind_from_path(path: &Path) -> Option<ShaderKind> {
+    extension_to_kind(path.extension()?.to_str()?)
+}
+
+/// Returns the path of the cache file next to `path`, its file name with `suffix` appended.
+///
+/// Only the file name is changed, so prefixes like `\\?\` and UNC shares are kept intact.
+#[cfg(feature = "fs")]
+fn sidecar_path(path: &Path, suffix: &str) -> PathBuf {
+    match path.file_name() {
+        Some(file_name) => {
+            let mut file_name = OsString::from(file_name);
+            file_name.push(suffix);
+            path.with_file_name(file_name)
+        }
+        None => {
+            let mut precompiled = OsString::from(path.as_os_str());
+            precompiled.push(suffix);
+            PathBuf::from(precompiled)
+        }
+    }
+}
+
+/// Fails with a `LoadError` of kind `InvalidData` if `source` contains a NUL character.
+/// shaderc panics on those instead of reporting an error.
+fn reject_nul(source: &str, name: &str) -> Result<(), CompilerError> {
+    match source.find('\0') {
+        Some(position) => Err(CompilerError::LoadError(Arc::new(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: contains a NUL character at byte {}", name, position),
+        )))),
+        None => Ok(()),
+    }
+}
+
+/// Adds `path` to `include_dirs`, canonicalized when possible, unless it is already in there.
+#[cfg(feature = "fs")]
+fn push_include_dir(include_dirs: &mut Vec<PathBuf>, path: &Path) {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if !include_dirs.contains(&path) {
+        include_dirs.push(path);
+    }
+}
+
+/// Escapes the characters Make treats specially in the targets and prerequisites of a rule.
+#[cfg(feature = "fs")]
+fn escape_make_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' | '#' => escaped.push('\\'),
+            '$' => escaped.push('$'),
+            _ => {}
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Converts SPIR-V words to little-endian bytes.
+fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().copied().flat_map(u32::to_le_bytes).collect()
+}
+
+/// Formats `binary` as a C/C++ header defining it as a `uint32_t` array named `var_name`,
+/// with its length in words as `<var_name>_len`.
+pub fn emit_c_header(binary: &[u32], var_name: &str) -> String {
+    let mut header = format!(
+        "#pragma once\n#include <stdint.h>\n\nstatic const uint32_t {}[] = {{\n",
+        var_name
+    );
+    for line in binary.chunks(8) {
+        let words: Vec<String> = line.iter().map(|word| format!("{:#010x}", word)).collect();
+        header.push_str(&format!("    {},\n", words.join(", ")));
+    }
+    header.push_str(&format!(
+        "}};\nstatic const uint32_t {}_len = {};\n",
+        var_name,
+        binary.len()
+    ));
+    header
+}
+
+/// Compiles the GLSL `source` with a default compiler, for one-off use without setting up a
+/// `Compiler`.
+pub fn compile_glsl(source: &str, kind: ShaderKind) -> Result<Vec<u32>, CompilerError> {
+    Compiler::new()?.compile_from_string(source, kind)
+}
+
+/// Compiles the file at `path` with a default compiler, bypassing the caches.
+#[cfg(feature = "fs")]
+pub fn compile_file<P: AsRef<Path>>(path: P, kind: ShaderKind) -> Result<Vec<u32>, CompilerError> {
+    Compiler::new()?.compile_from_file(path, kind, false)
+}
+
+const DEFAULT_MAX_INCLUDE_DEPTH: usize = 32;
+const DEFAULT_CACHE_EXTENSION: &str = ".spv";
+/// `shaderc::EnvVersion` has no Vulkan 1.3 variant yet, this uses the same encoding.
+const VULKAN_1_3: u32 = (1 << 22) | (3 << 12);
+/// The value of the deprecated `shaderc::EnvVersion::WebGPU`, which follows `OpenGL4_5`.
+const WEBGPU: u32 = shaderc::EnvVersion::OpenGL4_5 as u32 + 1;
+
+#[cfg(feature = "fs")]
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+#[cfg(feature = "fs")]
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a, used over `DefaultHasher` as its output is persisted in cache files.
+#[cfg(feature = "fs")]
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// An option applied to `shaderc::CompileOptions`.
+///
+/// The options object itself is opaque, so every applied option is recorded as well
+/// to be able to tell which configuration a binary was compiled with.
+#[derive(Debug, Clone, PartialEq)]
+enum CompileSetting {
+    TargetSpirv(SpirvVersion),
+    Macro(String, Option<String>),
+    AutoBindUniforms(bool),
+    AutoCombinedImageSampler(bool),
+    AutoMapLocations(bool),
+    BindingBase(ResourceKind, u32),
+    GenerateDebugInfo,
+    ForcedVersionProfile(u32, GlslProfile),
+    TargetEnv(TargetEnv, u32),
+    HlslIoMapping(bool),
+    HlslRegisterSetAndBinding(String, String, String),
+    HlslOffsets(bool),
+    SourceLanguage(SourceLanguage),
+    BindingBaseForStage(ShaderKind, ResourceKind, u32),
+    OptimizationLevel(OptimizationLevel),
+    SuppressWarnings,
+    WarningsAsErrors,
+    Limit(Limit, i32),
+    NanClamp(bool),
+    InvertY(bool),
+    /// Applied to the source rather than the options, see `Compiler::prepare_source`.
+    DefaultVersionProfile(u32, GlslProfile),
+    /// Applied to the source rather than the options, see `Compiler::prepare_source`.
+    Preamble(String),
+    /// Applied to the compiled binary, see `Compiler::validated`.
+    StripDebugSource,
+    /// Applied per file, see `Compiler::with_file_options`.
+    SourceLanguageAuto,
+}
+
+impl CompileSetting {
+    fn apply(&self, options: &mut shaderc::CompileOptions) {
+        match self {
+            CompileSetting::TargetSpirv(version) => options.set_target_spirv(*version),
+            CompileSetting::Macro(name, value) => {
+                options.add_macro_definition(name.as_str(), value.as_deref())
+            }
+            CompileSetting::AutoBindUniforms(auto_bind) => {
+                options.set_auto_bind_uniforms(*auto_bind)
+            }
+            CompileSetting::AutoCombinedImageSampler(auto_combine) => {
+                options.set_auto_combined_image_sampler(*auto_combine)
+            }
+            CompileSetting::AutoMapLocations(auto_map) => options.set_auto_map_locations(*auto_map),
+            CompileSetting::BindingBase(kind, base) => options.set_binding_base(*kind, *base),
+            CompileSetting::GenerateDebugInfo => options.set_generate_debug_info(),
+            CompileSetting::ForcedVersionProfile(version, profile) => {
+                options.set_forced_version_profile(*version, *profile)
+            }
+            CompileSetting::TargetEnv(env, version) => options.set_target_env(*env, *version),
+            CompileSetting::HlslIoMapping(iomap) => options.set_hlsl_io_mapping(*iomap),
+            CompileSetting::HlslRegisterSetAndBinding(register, set, binding) => options
+                .set_hlsl_register_set_and_binding(
+                    register.as_str(),
+                    set.as_str(),
+                    binding.as_str(),
+                ),
+            CompileSetting::HlslOffsets(offsets) => options.set_hlsl_offsets(*offsets),
+            CompileSetting::SourceLanguage(lang) => options.set_source_language(*lang),
+            CompileSetting::BindingBaseForStage(kind, resource_kind, base) => {
+                options.set_binding_base_for_stage(*kind, *resource_kind, *base)
+            }
+            CompileSetting::OptimizationLevel(level) => options.set_optimization_level(*level),
+            CompileSetting::SuppressWarnings => options.set_suppress_warnings(),
+            CompileSetting::WarningsAsErrors => options.set_warnings_as_errors(),
+            CompileSetting::Limit(limit, value) => options.set_limit(*limit, *value),
+            CompileSetting::NanClamp(enable) => options.set_nan_clamp(*enable),
+            CompileSetting::InvertY(enable) => options.set_invert_y(*enable),
+            CompileSetting::DefaultVersionProfile(_, _)
+            | CompileSetting::Preamble(_)
+            | CompileSetting::StripDebugSource
+            | CompileSetting::SourceLanguageAuto => {}
+        }
+    }
+}
+
+/// I/O errors are kept behind an `Arc` as `std::io::Error` can't be cloned.
 #[derive(Debug, Clone)]
 pub enum CompilerError {
     Log(CompilationError),
-    LoadError(String),
-    WriteError(String),
+    LoadError(Arc<io::Error>),
+    WriteError(Arc<io::Error>),
+    InitError(String),
+    UnknownShaderKind(String),
+    /// The compiled binary failed validation, see `CompilerBuilder::with_validate`.
+    Validation(String),
+    /// An `#include` in `requested_by` could not be found in any of the `searched`
+    /// directories.
+    IncludeNotFound {
+        requested: String,
+        requested_by: String,
+        searched: Vec<PathBuf>,
+    },
 }
 
 impl Display for CompilerError {
@@ -30,19 +332,114 @@ impl Display for CompilerError {
                 CompilerError::Log(e) => format!("{}", e),
                 CompilerError::LoadError(e) => format!("could not load file: {}", e),
                 CompilerError::WriteError(e) => format!("could not write file: {}", e),
+                CompilerError::InitError(e) => format!("could not initialize compiler: {}", e),
+                CompilerError::UnknownShaderKind(e) => format!(
+                    "could not infer shader kind of {}, supported extensions are: {}",
+                    e,
+                    SHADER_EXTENSIONS
+                        .iter()
+                        .map(|(ext, _)| *ext)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                CompilerError::Validation(e) => format!("SPIR-V validation failed: {}", e),
+                CompilerError::IncludeNotFound {
+                    requested,
+                    requested_by,
+                    searched,
+                } => format!(
+                    "could not find include {} requested by {}, searched: {}",
+                    requested,
+                    requested_by,
+                    searched
+                        .iter()
+                        .map(|dir| dir.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
             }
         )
     }
 }
 
-impl Error for CompilerError {}
+impl Error for CompilerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CompilerError::LoadError(e) | CompilerError::WriteError(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct CompilationError {
     pub file: Option<PathBuf>,
+    /// The stage and entry point that were compiled, `None` when only preprocessing.
+    pub kind: Option<ShaderKind>,
+    pub entry_point: Option<String>,
     pub description: String,
 }
 
+impl CompilationError {
+    /// Parses the description into individual diagnostics, one for every error and warning
+    /// shaderc reported for the compile.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        parse_diagnostics(self.description.as_str())
+    }
+
+    /// Prints every diagnostic as a `cargo:warning=` line, for use in build scripts. Falls back
+    /// to the lines of the description if it holds no diagnostics.
+    pub fn emit_cargo_warnings(&self) {
+        let diagnostics = self.diagnostics();
+        if diagnostics.is_empty() {
+            for line in self
+                .description
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+            {
+                println!("cargo:warning={}", line);
+            }
+        }
+        for diagnostic in diagnostics {
+            println!("{}", diagnostic.to_cargo_warning());
+        }
+    }
+}
+
+/// The `#version` directive of a source.
+struct VersionDirective {
+    /// Byte offset of the end of the directive's line, including its newline.
+    end: usize,
+    version: u32,
+    es: bool,
+}
+
+/// Finds the first `#version` directive in `source`.
+fn version_directive(source: &str) -> Option<VersionDirective> {
+    let mut start = 0;
+    for line in source.split_inclusive('\n') {
+        start += line.len();
+        let directive = line
+            .trim_start()
+            .strip_prefix('#')
+            .and_then(|directive| directive.trim_start().strip_prefix("version"));
+        if let Some(directive) = directive {
+            let mut tokens = directive.split_whitespace();
+            return Some(VersionDirective {
+                end: start,
+                version: tokens.next().and_then(|v| v.parse().ok()).unwrap_or(0),
+                es: tokens.next() == Some("es"),
+            });
+        }
+    }
+    None
+}
+
+/// Resolves `path` for `CompilationError.file`, so tools can open it from any directory.
+fn error_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
 impl From<CompilationError> for CompilerError {
     fn from(val: CompilationError) -> Self {
         CompilerError::Log(val)
@@ -51,24 +448,340 @@ impl From<CompilationError> for CompilerError {
 
 impl Display for CompilationError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let message = if let Some(file) = self.file.as_ref() {
-            format!(
-                "file: {}, description: {}",
-                file.display(),
-                self.description.as_str(),
-            )
-        } else {
-            format!("description: {}", self.description.as_str())
-        };
+        if let Some(file) = self.file.as_ref() {
+            write!(f, "file: {}, ", file.display())?;
+        }
+        if let Some(kind) = self.kind {
+            write!(f, "kind: {:?}, ", kind)?;
+        }
+        if let Some(entry_point) = self.entry_point.as_ref() {
+            write!(f, "entry point: {}, ", entry_point)?;
+        }
+        write!(f, "description: {}", self.description.as_str())
+    }
+}
+
+/// A compiled binary together with the warnings produced while compiling it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileOutput {
+    pub binary: Vec<u32>,
+    pub warning_count: u32,
+    pub warnings: String,
+}
+
+impl From<Vec<u32>> for CompileOutput {
+    fn from(binary: Vec<u32>) -> Self {
+        CompileOutput {
+            binary,
+            warning_count: 0,
+            warnings: String::new(),
+        }
+    }
+}
+
+/// The results of `Compiler::compile_many`, split into the files that compiled and those
+/// that failed. Both keep the order the files were given in.
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone, Default)]
+pub struct BatchResult {
+    pub succeeded: Vec<(PathBuf, Vec<u32>)>,
+    pub failed: Vec<(PathBuf, CompilerError)>,
+    /// Whether each input succeeded, in input order, to interleave the two lists again.
+    order: Vec<bool>,
+}
+
+#[cfg(feature = "fs")]
+impl BatchResult {
+    pub fn is_all_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Returns the first file that failed to compile with its error.
+    pub fn first_error(&self) -> Option<&(PathBuf, CompilerError)> {
+        self.failed.first()
+    }
+
+    /// Returns every result as a flat list, in the order the files were given in.
+    pub fn into_results(self) -> Vec<(PathBuf, Result<Vec<u32>, CompilerError>)> {
+        let mut succeeded = self
+            .succeeded
+            .into_iter()
+            .map(|(path, binary)| (path, Ok(binary)));
+        let mut failed = self.failed.into_iter().map(|(path, e)| (path, Err(e)));
+        let mut results: Vec<_> = self
+            .order
+            .iter()
+            .filter_map(|ok| if *ok { succeeded.next() } else { failed.next() })
+            .collect();
+        // Entries pushed onto the public lists directly have no recorded position
+        results.extend(succeeded.chain(failed));
+        results
+    }
+}
+
+#[cfg(feature = "fs")]
+impl FromIterator<(PathBuf, Result<Vec<u32>, CompilerError>)> for BatchResult {
+    fn from_iter<I: IntoIterator<Item = (PathBuf, Result<Vec<u32>, CompilerError>)>>(
+        iter: I,
+    ) -> Self {
+        let mut result = BatchResult::default();
+        for (path, binary) in iter {
+            result.order.push(binary.is_ok());
+            match binary {
+                Ok(binary) => result.succeeded.push((path, binary)),
+                Err(e) => result.failed.push((path, e)),
+            }
+        }
+        result
+    }
+}
+
+impl From<&shaderc::CompilationArtifact> for CompileOutput {
+    fn from(artifact: &shaderc::CompilationArtifact) -> Self {
+        CompileOutput {
+            binary: artifact.as_binary().to_vec(),
+            warning_count: artifact.get_num_warnings(),
+            warnings: artifact.get_warning_messages(),
+        }
+    }
+}
+
+type IncludeResolverFn =
+    dyn Fn(&str, IncludeType, &str, usize) -> Result<ResolvedInclude, String> + Send + Sync;
+
+/// An include resolver set through `CompilerBuilder::with_include_resolver`.
+#[derive(Clone)]
+struct CustomIncludeResolver(Arc<IncludeResolverFn>);
+
+impl Debug for CustomIncludeResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CustomIncludeResolver")
+    }
+}
+
+/// Where the warnings of `Compiler::compile_from_file` and similar methods are reported,
+/// see `CompilerBuilder::with_warning_sink`.
+#[derive(Clone, Default)]
+pub enum WarningSink {
+    #[default]
+    Stderr,
+    Silent,
+    /// Kept behind an `Arc` so compilers with this sink can still be cloned.
+    Callback(Arc<dyn Fn(&str) + Send + Sync>),
+}
+
+impl WarningSink {
+    pub fn callback<F: Fn(&str) + Send + Sync + 'static>(callback: F) -> Self {
+        WarningSink::Callback(Arc::new(callback))
+    }
+
+    fn report(&self, message: &str) {
+        match self {
+            WarningSink::Stderr => eprintln!("{}", message),
+            WarningSink::Silent => {}
+            WarningSink::Callback(callback) => callback(message),
+        }
+    }
+}
+
+impl Debug for WarningSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WarningSink::Stderr => write!(f, "Stderr"),
+            WarningSink::Silent => write!(f, "Silent"),
+            WarningSink::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
+/// Measurements of a single compile, passed to the observer set through
+/// `CompilerBuilder::with_compile_observer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompileStats {
+    pub duration: Duration,
+    pub word_count: usize,
+    pub kind: ShaderKind,
+    pub cache_hit: bool,
+}
+
+#[derive(Clone)]
+struct CompileObserver(Arc<dyn Fn(&CompileStats) + Send + Sync>);
+
+impl Debug for CompileObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CompileObserver")
+    }
+}
+
+/// An include that could not be found, see `CompilerError::IncludeNotFound`.
+#[derive(Debug, Clone, PartialEq)]
+struct IncludeFailure {
+    requested: String,
+    requested_by: String,
+    searched: Vec<PathBuf>,
+}
+
+/// The chain of sources currently being included, used to detect include cycles.
+#[derive(Debug, Default)]
+struct IncludeChain {
+    sources: Vec<String>,
+    /// Requested source and error of a cycle found through a relative include. shaderc retries
+    /// failed relative includes as standard includes, which would otherwise hide this error.
+    relative_cycle: Option<(String, String)>,
+}
+
+impl IncludeChain {
+    /// Turns a successfully resolved include that is already being included twice into an error.
+    ///
+    /// Including a file once more while it's being included is fine when it has include guards,
+    /// as its content is skipped then. Without guards the cycle repeats, which is caught here
+    /// well before the include depth limit is reached.
+    fn check(
+        &mut self,
+        result: shaderc::IncludeCallbackResult,
+        requested_source: &str,
+        include_type: shaderc::IncludeType,
+        requesting_source: &str,
+        include_depth: usize,
+    ) -> shaderc::IncludeCallbackResult {
+        // The requesting source is the one included at depth - 1, where depth 0 is the root
+        self.sources.truncate(include_depth.saturating_sub(1));
+        self.sources.push(String::from(requesting_source));
+
+        let relative_cycle = self.relative_cycle.take();
+        match result {
+            Ok(include) => {
+                let occurrences: Vec<usize> = self
+                    .sources
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, source)| Self::same_source(source, &include.resolved_name))
+                    .map(|(index, _)| index)
+                    .collect();
+                if occurrences.len() >= 2 {
+                    let start = occurrences[occurrences.len() - 1];
+                    let mut cycle = self.sources[start..].to_vec();
+                    cycle.push(include.resolved_name);
+                    let message = format!("Include cycle detected: {}", cycle.join(" -> "));
+                    if include_type == shaderc::IncludeType::Relative {
+                        self.relative_cycle =
+                            Some((String::from(requested_source), message.clone()));
+                    }
+                    Err(message)
+                } else {
+                    Ok(include)
+                }
+            }
+            Err(e) => match relative_cycle {
+                Some((requested, message)) if requested == requested_source => Err(message),
+                _ => Err(e),
+            },
+        }
+    }
 
-        write!(f, "{}", message)
+    fn same_source(a: &str, b: &str) -> bool {
+        if a == b {
+            return true;
+        }
+        match (Path::new(a).canonicalize(), Path::new(b).canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
     }
 }
 
+/// The value of a SPIR-V specialization constant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpecValue {
+    Bool(bool),
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+    Double(f64),
+}
+
+/// Decides when a cached `.spv` file is considered up to date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheStrategy {
+    /// Up to date when it was modified after the source file, and compiled with the same options.
+    #[default]
+    Mtime,
+    /// Up to date when the preprocessed source, so including all includes, and the options
+    /// are the same as when it was compiled. Slower, but independent of file timestamps.
+    /// Macros the source doesn't use don't invalidate its binary.
+    ContentHash,
+}
+
+/// How much debug information compiled binaries contain, see
+/// `CompilerBuilder::with_debug_info_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugInfoMode {
+    /// Names, file and line information and the source text.
+    Full,
+    /// Like `Full`, without the source text.
+    LinesOnly,
+    None,
+}
+
+/// Where the binary of the last compile came from, see `Compiler::last_compile_source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompileSource {
+    InMemoryCache,
+    DiskCache,
+    #[default]
+    Compiled,
+}
+
 pub struct CompilerBuilder<'a> {
     options: shaderc::CompileOptions<'a>,
     include_dirs: Vec<PathBuf>,
-    has_macros: bool,
+    virtual_includes: HashMap<String, String>,
+    max_include_depth: usize,
+    virtual_base_dir: Option<PathBuf>,
+    custom_resolver: Option<CustomIncludeResolver>,
+    cache_strategy: CacheStrategy,
+    cache_dir: Option<PathBuf>,
+    cache_extension: String,
+    spec_constants: HashMap<u32, SpecValue>,
+    #[cfg(feature = "fs")]
+    cache_capacity: Option<usize>,
+    settings: Vec<CompileSetting>,
+    entry_point: String,
+    stage_entry_points: Vec<(ShaderKind, String)>,
+    validate: bool,
+    infer_fallback: bool,
+    collect_warnings: bool,
+    warning_sink: WarningSink,
+    compile_observer: Option<CompileObserver>,
+}
+
+impl Clone for CompilerBuilder<'_> {
+    fn clone(&self) -> Self {
+        // `CompileOptions::clone` ties the clone to the lifetime of the borrow, replaying the
+        // recorded settings gives the same options without that restriction
+        CompilerBuilder {
+            options: Self::options_from_settings(&self.settings),
+            include_dirs: self.include_dirs.clone(),
+            virtual_includes: self.virtual_includes.clone(),
+            max_include_depth: self.max_include_depth,
+            virtual_base_dir: self.virtual_base_dir.clone(),
+            custom_resolver: self.custom_resolver.clone(),
+            cache_strategy: self.cache_strategy,
+            cache_dir: self.cache_dir.clone(),
+            cache_extension: self.cache_extension.clone(),
+            spec_constants: self.spec_constants.clone(),
+            #[cfg(feature = "fs")]
+            cache_capacity: self.cache_capacity,
+            settings: self.settings.clone(),
+            entry_point: self.entry_point.clone(),
+            stage_entry_points: self.stage_entry_points.clone(),
+            validate: self.validate,
+            infer_fallback: self.infer_fallback,
+            collect_warnings: self.collect_warnings,
+            warning_sink: self.warning_sink.clone(),
+            compile_observer: self.compile_observer.clone(),
+        }
+    }
 }
 
 impl Default for CompilerBuilder<'_> {
@@ -82,455 +795,4151 @@ impl<'a> CompilerBuilder<'a> {
         CompilerBuilder {
             options: shaderc::CompileOptions::new().unwrap(),
             include_dirs: Vec::new(),
-            has_macros: false,
+            virtual_includes: HashMap::new(),
+            max_include_depth: DEFAULT_MAX_INCLUDE_DEPTH,
+            virtual_base_dir: None,
+            custom_resolver: None,
+            cache_strategy: CacheStrategy::default(),
+            cache_dir: None,
+            cache_extension: String::from(DEFAULT_CACHE_EXTENSION),
+            spec_constants: HashMap::new(),
+            #[cfg(feature = "fs")]
+            cache_capacity: None,
+            settings: Vec::new(),
+            entry_point: String::from("main"),
+            stage_entry_points: Vec::new(),
+            validate: false,
+            infer_fallback: false,
+            collect_warnings: false,
+            warning_sink: WarningSink::default(),
+            compile_observer: None,
         }
     }
 
-    pub fn with_target_spirv(mut self, version: SpirvVersion) -> Self {
-        self.options.set_target_spirv(version);
-        self
+    fn options_from_settings(settings: &[CompileSetting]) -> shaderc::CompileOptions<'a> {
+        let mut options = shaderc::CompileOptions::new().unwrap();
+        for setting in settings.iter() {
+            setting.apply(&mut options);
+        }
+        options
     }
 
-    pub fn with_macro(mut self, name: &str, value: Option<&str>) -> Self {
-        self.options.add_macro_definition(name, value);
-        self.has_macros = true;
+    fn set(mut self, setting: CompileSetting) -> Self {
+        setting.apply(&mut self.options);
+        self.settings.push(setting);
         self
     }
 
-    pub fn with_auto_bind_uniforms(mut self, auto_bind: bool) -> Self {
-        self.options.set_auto_bind_uniforms(auto_bind);
-        self
+    pub fn with_target_spirv(self, version: SpirvVersion) -> Self {
+        self.set(CompileSetting::TargetSpirv(version))
     }
 
-    pub fn with_binding_base(mut self, kind: ResourceKind, base: u32) -> Self {
-        self.options.set_binding_base(kind, base);
-        self
+    pub fn with_macro(self, name: &str, value: Option<&str>) -> Self {
+        self.set(CompileSetting::Macro(
+            String::from(name),
+            value.map(String::from),
+        ))
     }
 
-    pub fn generate_debug_info(mut self) -> Self {
-        self.options.set_generate_debug_info();
+    /// Sets the name of the entry point function, defaults to `"main"`.
+    pub fn with_entry_point(mut self, name: &str) -> Self {
+        self.entry_point = String::from(name);
         self
     }
 
-    pub fn force_version_profile(mut self, version: u32, profile: shaderc::GlslProfile) -> Self {
-        self.options.set_forced_version_profile(version, profile);
+    /// Sets the entry point `Compiler::compile_from_file_auto` uses for each stage, such as
+    /// `VSMain` for vertex shaders. Stages without one use the entry point of
+    /// `with_entry_point`.
+    pub fn with_stage_entry_points<I, S>(mut self, entry_points: I) -> Self
+    where
+        I: IntoIterator<Item = (ShaderKind, S)>,
+        S: Into<String>,
+    {
+        for (kind, name) in entry_points {
+            self.stage_entry_points.retain(|(k, _)| *k != kind);
+            self.stage_entry_points.push((kind, name.into()));
+        }
         self
     }
 
-    pub fn with_target_env(mut self, env: shaderc::TargetEnv, version: u32) -> Self {
-        self.options.set_target_env(env, version);
-        self
+    pub fn with_auto_bind_uniforms(self, auto_bind: bool) -> Self {
+        self.set(CompileSetting::AutoBindUniforms(auto_bind))
+    }
+
+    /// Compiles separate textures and samplers in HLSL into combined image samplers.
+    pub fn with_auto_combined_image_sampler(self, auto_combine: bool) -> Self {
+        self.set(CompileSetting::AutoCombinedImageSampler(auto_combine))
+    }
+
+    /// Assigns locations to shader inputs and outputs without a `layout(location)`.
+    pub fn with_auto_map_locations(self, auto_map: bool) -> Self {
+        self.set(CompileSetting::AutoMapLocations(auto_map))
+    }
+
+    pub fn with_binding_base(self, kind: ResourceKind, base: u32) -> Self {
+        self.set(CompileSetting::BindingBase(kind, base))
+    }
+
+    #[deprecated(note = "use `with_debug_info(true)` instead")]
+    pub fn generate_debug_info(self) -> Self {
+        self.with_debug_info(true)
     }
 
-    pub fn with_hlsl_io_mapping(mut self, iomap: bool) -> Self {
-        self.options.set_hlsl_io_mapping(iomap);
+    /// Enables or disables debug info, such as names and source lines, in the binary.
+    pub fn with_debug_info(mut self, debug_info: bool) -> Self {
+        let enabled = self.settings.contains(&CompileSetting::GenerateDebugInfo);
+        if debug_info && !enabled {
+            self = self.set(CompileSetting::GenerateDebugInfo);
+        } else if !debug_info && enabled {
+            // shaderc can't turn debug info off again, rebuild the options without it
+            self.settings
+                .retain(|setting| *setting != CompileSetting::GenerateDebugInfo);
+            self.options = Self::options_from_settings(&self.settings);
+        }
         self
     }
 
+    /// Sets how much debug info binaries contain. `DebugInfoMode::Full` is the same as
+    /// `with_debug_info(true)`. shaderc always embeds the source text with debug info, for
+    /// `DebugInfoMode::LinesOnly` it is removed from the binary after compiling.
+    pub fn with_debug_info_mode(mut self, mode: DebugInfoMode) -> Self {
+        self.settings
+            .retain(|setting| *setting != CompileSetting::StripDebugSource);
+        match mode {
+            DebugInfoMode::Full => self.with_debug_info(true),
+            DebugInfoMode::LinesOnly => self
+                .with_debug_info(true)
+                .set(CompileSetting::StripDebugSource),
+            DebugInfoMode::None => self.with_debug_info(false),
+        }
+    }
+
+    pub fn force_version_profile(self, version: u32, profile: shaderc::GlslProfile) -> Self {
+        self.set(CompileSetting::ForcedVersionProfile(version, profile))
+    }
+
+    /// Prepends `preamble`, such as `#extension` directives or macros, to every source. It is
+    /// inserted after the `#version` directive, and line numbers in messages still refer to
+    /// the original source. Preambles of multiple calls are inserted in order.
+    pub fn with_preamble(self, preamble: &str) -> Self {
+        self.set(CompileSetting::Preamble(String::from(preamble)))
+    }
+
+    /// Compiles GLSL sources without a `#version` directive as if they started with
+    /// `#version <version> <profile>`. Unlike `force_version_profile`, sources that declare a
+    /// version are left alone.
+    pub fn with_default_version_profile(self, version: u32, profile: GlslProfile) -> Self {
+        self.set(CompileSetting::DefaultVersionProfile(version, profile))
+    }
+
+    pub fn with_target_env(self, env: shaderc::TargetEnv, version: u32) -> Self {
+        self.set(CompileSetting::TargetEnv(env, version))
+    }
+
+    pub fn with_hlsl_io_mapping(self, iomap: bool) -> Self {
+        self.set(CompileSetting::HlslIoMapping(iomap))
+    }
+
     pub fn with_hlsl_register_set_and_binding(
-        mut self,
+        self,
         register: &str,
         set: &str,
         binding: &str,
     ) -> Self {
-        self.options
-            .set_hlsl_register_set_and_binding(register, set, binding);
-        self
+        self.set(CompileSetting::HlslRegisterSetAndBinding(
+            String::from(register),
+            String::from(set),
+            String::from(binding),
+        ))
     }
 
-    pub fn with_hlsl_offsets(mut self, offsets: bool) -> Self {
-        self.options.set_hlsl_offsets(offsets);
-        self
+    pub fn with_hlsl_offsets(self, offsets: bool) -> Self {
+        self.set(CompileSetting::HlslOffsets(offsets))
     }
 
-    pub fn with_source_language(mut self, lang: SourceLanguage) -> Self {
-        self.options.set_source_language(lang);
-        self
+    /// Targets Vulkan 1.0 and SPIR-V 1.0.
+    pub fn with_vulkan_1_0(self) -> Self {
+        self.with_target_env(TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_0 as u32)
+            .with_target_spirv(SpirvVersion::V1_0)
+    }
+
+    /// Targets Vulkan 1.1 and SPIR-V 1.3.
+    pub fn with_vulkan_1_1(self) -> Self {
+        self.with_target_env(TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_1 as u32)
+            .with_target_spirv(SpirvVersion::V1_3)
+    }
+
+    /// Targets Vulkan 1.2 and SPIR-V 1.5.
+    pub fn with_vulkan_1_2(self) -> Self {
+        self.with_target_env(TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_2 as u32)
+            .with_target_spirv(SpirvVersion::V1_5)
+    }
+
+    /// Targets Vulkan 1.3 and SPIR-V 1.6.
+    ///
+    /// Versions of shaderc that predate Vulkan 1.3 don't know its environment and compile
+    /// with Vulkan 1.0 semantics instead.
+    pub fn with_vulkan_1_3(self) -> Self {
+        self.with_target_env(TargetEnv::Vulkan, VULKAN_1_3)
+            .with_target_spirv(SpirvVersion::V1_6)
+    }
+
+    /// Targets OpenGL 4.5 and SPIR-V 1.0.
+    pub fn with_opengl(self) -> Self {
+        self.with_target_env(TargetEnv::OpenGL, shaderc::EnvVersion::OpenGL4_5 as u32)
+            .with_target_spirv(SpirvVersion::V1_0)
+    }
+
+    /// Targets the WebGPU flavor of SPIR-V 1.0. shaderc can't emit WGSL.
+    ///
+    /// Support for this environment was dropped from shaderc, `build` checks whether the
+    /// linked version still accepts it and returns `CompilerError::InitError` otherwise.
+    pub fn with_webgpu(self) -> Self {
+        self.with_target_env(TargetEnv::Vulkan, WEBGPU)
+            .with_target_spirv(SpirvVersion::V1_0)
+    }
+
+    /// Targets Vulkan 1.2 and SPIR-V 1.4, the minimum for the ray tracing stages.
+    pub fn with_raytracing_env(self) -> Self {
+        self.with_vulkan_1_2().with_target_spirv(SpirvVersion::V1_4)
+    }
+
+    /// Sets up HLSL compilation for Vulkan: HLSL source, register to binding mapping, HLSL
+    /// packing offsets and automatic bindings for resources without an explicit register.
+    /// Registers that need a specific set and binding can be mapped with
+    /// `with_hlsl_register_set_and_binding`.
+    pub fn with_hlsl_vulkan_preset(self) -> Self {
+        self.with_source_language(SourceLanguage::HLSL)
+            .with_hlsl_io_mapping(true)
+            .with_hlsl_offsets(true)
+            .with_auto_bind_uniforms(true)
+    }
+
+    pub fn with_source_language(self, lang: SourceLanguage) -> Self {
+        self.set(CompileSetting::SourceLanguage(lang))
+    }
+
+    /// Compiles files with a `.hlsl` or `.hls` extension as HLSL and any other file as GLSL,
+    /// overriding `with_source_language`. Sources compiled from strings are unaffected.
+    pub fn with_source_language_auto(self) -> Self {
+        self.set(CompileSetting::SourceLanguageAuto)
     }
 
     pub fn with_binding_base_for_stage(
-        mut self,
+        self,
         kind: shaderc::ShaderKind,
         resource_kind: shaderc::ResourceKind,
         base: u32,
     ) -> Self {
-        self.options
-            .set_binding_base_for_stage(kind, resource_kind, base);
-        self
+        self.set(CompileSetting::BindingBaseForStage(
+            kind,
+            resource_kind,
+            base,
+        ))
     }
 
-    pub fn with_opt_level(mut self, level: OptimizationLevel) -> Self {
-        self.options.set_optimization_level(level);
-        self
+    pub fn with_opt_level(self, level: OptimizationLevel) -> Self {
+        self.set(CompileSetting::OptimizationLevel(level))
     }
 
-    pub fn supress_warnings(mut self) -> Self {
-        self.options.set_suppress_warnings();
-        self
+    /// The level set by the last `with_opt_level` call, shaderc defaults to `Zero`.
+    pub fn opt_level(&self) -> OptimizationLevel {
+        self.settings
+            .iter()
+            .rev()
+            .find_map(|setting| match setting {
+                CompileSetting::OptimizationLevel(level) => Some(*level),
+                _ => None,
+            })
+            .unwrap_or(OptimizationLevel::Zero)
     }
 
-    pub fn with_warnings_as_errors(mut self) -> Self {
-        self.options.set_warnings_as_errors();
-        self
+    pub fn supress_warnings(self) -> Self {
+        self.set(CompileSetting::SuppressWarnings)
     }
 
-    pub fn with_limit(mut self, limit: shaderc::Limit, value: i32) -> Self {
-        self.options.set_limit(limit, value);
-        self
+    pub fn with_warnings_as_errors(self) -> Self {
+        self.set(CompileSetting::WarningsAsErrors)
     }
 
-    pub fn with_include_dir<T: AsRef<Path>>(mut self, path: T) -> Self {
-        debug_assert!(path.as_ref().exists());
-        self.include_dirs.push(path.as_ref().to_path_buf());
-        self
+    pub fn with_limit(self, limit: shaderc::Limit, value: i32) -> Self {
+        self.set(CompileSetting::Limit(limit, value))
     }
 
-    pub fn build(self) -> Option<Compiler<'a>> {
-        if let Some(compiler) = shaderc::Compiler::new() {
-            let mut compiler = Compiler {
-                compiler,
-                options: self.options,
-                compile_cache: HashMap::new(),
-                include_dirs: Arc::new(Mutex::new(self.include_dirs)),
-                has_macros: self.has_macros,
-            };
-
-            let include_dirs = compiler.include_dirs.clone();
-            compiler.options.set_include_callback(
-                move |requested_source, include_type, requesting_source, include_depth| {
-                    Compiler::include_callback(
-                        include_dirs.lock().unwrap().as_slice(),
-                        requested_source,
-                        include_type,
-                        requesting_source,
-                        include_depth,
-                    )
-                },
-            );
+    /// Sets every limit in `limits`, see `with_limit`.
+    pub fn with_limits<I: IntoIterator<Item = (Limit, i32)>>(self, limits: I) -> Self {
+        limits.into_iter().fold(self, |builder, (limit, value)| {
+            builder.with_limit(limit, value)
+        })
+    }
 
-            Some(compiler)
-        } else {
-            None
-        }
+    /// Makes the `min`, `max` and `clamp` builtins favour non-NaN operands.
+    ///
+    /// Requires shaderc v2019.1 or newer, which introduced `-fnan-clamp`.
+    pub fn with_nan_clamp(self, nan_clamp: bool) -> Self {
+        self.set(CompileSetting::NanClamp(nan_clamp))
     }
-}
 
-pub struct Compiler<'a> {
-    compiler: shaderc::Compiler,
-    options: shaderc::CompileOptions<'a>,
-    compile_cache: HashMap<PathBuf, Vec<u32>>,
-    include_dirs: Arc<Mutex<Vec<PathBuf>>>,
-    has_macros: bool,
-}
+    /// Inverts the Y coordinate of the position output of vertex shaders, to convert between
+    /// the Vulkan and OpenGL clip space conventions.
+    pub fn with_invert_y(self, invert_y: bool) -> Self {
+        self.set(CompileSetting::InvertY(invert_y))
+    }
 
-impl Debug for Compiler<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Compiler")
-            .field("compile_cache", &self.compile_cache)
-            .field("include_dirs", &self.include_dirs)
-            .field("has_macros", &self.has_macros)
-            .finish()
+    /// Adds an include directory. Paths are canonicalized when possible and directories that
+    /// were already added are skipped.
+    #[cfg(feature = "fs")]
+    pub fn with_include_dir<T: AsRef<Path>>(mut self, path: T) -> Self {
+        push_include_dir(&mut self.include_dirs, path.as_ref());
+        self
     }
-}
 
-impl<'a> Compiler<'a> {
-    pub fn new() -> Option<Compiler<'a>> {
-        if let Some(compiler) = shaderc::Compiler::new() {
-            return Some(Compiler {
-                compiler,
-                options: shaderc::CompileOptions::new().unwrap(),
-                compile_cache: HashMap::new(),
-                include_dirs: Arc::new(Mutex::new(Vec::new())),
-                has_macros: false,
-            });
+    /// Like `with_include_dir`, but fails if `path` is not an existing directory.
+    #[cfg(feature = "fs")]
+    pub fn try_with_include_dir<T: AsRef<Path>>(self, path: T) -> Result<Self, CompilerError> {
+        if !path.as_ref().is_dir() {
+            return Err(CompilerError::LoadError(Arc::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "include directory does not exist: {}",
+                    path.as_ref().display()
+                ),
+            ))));
         }
-        None
+        Ok(self.with_include_dir(path))
+    }
+
+    /// Adds every path in `paths` as an include directory, searched in the given order.
+    #[cfg(feature = "fs")]
+    pub fn with_include_dirs<I, T>(self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<Path>,
+    {
+        paths
+            .into_iter()
+            .fold(self, |builder, path| builder.with_include_dir(path))
+    }
+
+    /// Registers an in-memory file that `#include` directives resolve to `content`.
+    ///
+    /// Virtual includes are resolved before any include directory is searched.
+    pub fn with_virtual_include(mut self, name: &str, content: String) -> Self {
+        self.virtual_includes.insert(String::from(name), content);
+        self
+    }
+
+    /// Resolves includes with `resolver` instead of searching the include directories and
+    /// virtual includes. Include cycles and the maximum include depth are still checked.
+    pub fn with_include_resolver<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&str, IncludeType, &str, usize) -> Result<ResolvedInclude, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.custom_resolver = Some(CustomIncludeResolver(Arc::new(resolver)));
+        self
+    }
+
+    /// Sets how deeply includes may be nested, defaults to 32.
+    pub fn with_max_include_depth(mut self, depth: usize) -> Self {
+        self.max_include_depth = depth;
+        self
+    }
+
+    /// Resolves `#include "file"` in sources compiled from memory against `dir`, as they have
+    /// no directory of their own. A source named `gen/a.frag` resolves against `dir/gen`.
+    #[cfg(feature = "fs")]
+    pub fn with_virtual_base_dir(mut self, dir: PathBuf) -> Self {
+        self.virtual_base_dir = Some(dir);
+        self
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn with_cache_strategy(mut self, strategy: CacheStrategy) -> Self {
+        self.cache_strategy = strategy;
+        self
+    }
+
+    /// Stores cached binaries in `dir` instead of next to their source files.
+    #[cfg(feature = "fs")]
+    pub fn with_cache_dir(mut self, dir: PathBuf) -> Self {
+        self.cache_dir = Some(dir);
+        self
+    }
+
+    /// Sets the suffix appended to a source path to get the path of its cached binary,
+    /// `.spv` by default.
+    #[cfg(feature = "fs")]
+    pub fn with_cache_extension(mut self, extension: &str) -> Self {
+        self.cache_extension = String::from(extension);
+        self
+    }
+
+    /// Limits the in-memory cache to `capacity` binaries, evicting the least recently used
+    /// binary when it is exceeded. The cache is unbounded by default.
+    #[cfg(feature = "fs")]
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Records the value of specialization constant `id`, see `Compiler::spec_constants`.
+    pub fn with_spec_constant(mut self, id: u32, value: SpecValue) -> Self {
+        self.spec_constants.insert(id, value);
+        self
+    }
+
+    /// Validates every binary compiled from source before returning it, failing with
+    /// `CompilerError::Validation`. Disabled by default.
+    ///
+    /// shaderc does not expose the SPIR-V validator, so this only checks the structure of the
+    /// binary: its header and that its instructions are well formed.
+    pub fn with_validate(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
+
+    /// Makes `Compiler::compile_from_file_auto` compile files without a stage extension, such as
+    /// `.glsl`, as `ShaderKind::InferFromSource`, reporting the fallback to the warning sink.
+    /// Such files fail with `CompilerError::UnknownShaderKind` by default.
+    pub fn with_infer_fallback(mut self, infer_fallback: bool) -> Self {
+        self.infer_fallback = infer_fallback;
+        self
+    }
+
+    /// Keeps the warnings of the last compile for `Compiler::take_last_warnings` instead of
+    /// printing them to stderr in `compile_from_file`.
+    pub fn with_collect_warnings(mut self, collect: bool) -> Self {
+        self.collect_warnings = collect;
+        self
+    }
+
+    /// Sets where warnings are reported when they aren't collected, stderr by default.
+    pub fn with_warning_sink(mut self, sink: WarningSink) -> Self {
+        self.warning_sink = sink;
+        self
+    }
+
+    /// Calls `observer` with the duration and output size of every successful
+    /// `Compiler::compile_from_file` and `Compiler::compile_from_string`, for build profiling.
+    pub fn with_compile_observer<F: Fn(&CompileStats) + Send + Sync + 'static>(
+        mut self,
+        observer: F,
+    ) -> Self {
+        self.compile_observer = Some(CompileObserver(Arc::new(observer)));
+        self
+    }
+
+    pub fn build(self) -> Result<Compiler<'a>, CompilerError> {
+        let (options, config) = self.into_parts();
+        Compiler::from_parts(options, config)
+    }
+
+    /// Builds a pool of compilers that can be used from any thread.
+    #[cfg(feature = "fs")]
+    pub fn build_pool(self) -> CompilerPool {
+        CompilerPool {
+            id: NEXT_POOL_ID.fetch_add(1, AtomicOrdering::Relaxed),
+            alive: Arc::new(()),
+            config: self.into_config(),
+        }
+    }
+
+    /// Converts the builder into plain data that, unlike the builder, can be sent to other
+    /// threads to build identically configured compilers there.
+    pub fn into_config(self) -> CompilerConfig {
+        self.into_parts().1
+    }
+
+    fn into_parts(self) -> (shaderc::CompileOptions<'a>, CompilerConfig) {
+        (
+            self.options,
+            CompilerConfig {
+                settings: self.settings,
+                include_dirs: Arc::new(Mutex::new(self.include_dirs)),
+                virtual_includes: Arc::new(Mutex::new(self.virtual_includes)),
+                max_include_depth: self.max_include_depth,
+                virtual_base_dir: self.virtual_base_dir,
+                custom_resolver: self.custom_resolver,
+                cache_strategy: self.cache_strategy,
+                cache_dir: self.cache_dir,
+                cache_extension: self.cache_extension,
+                spec_constants: self.spec_constants,
+                #[cfg(feature = "fs")]
+                cache_capacity: self.cache_capacity,
+                entry_point: self.entry_point,
+                stage_entry_points: self.stage_entry_points,
+                validate: self.validate,
+                infer_fallback: self.infer_fallback,
+                collect_warnings: self.collect_warnings,
+                warning_sink: self.warning_sink,
+                compile_observer: self.compile_observer,
+            },
+        )
+    }
+}
+
+#[cfg(feature = "fs")]
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "fs")]
+thread_local! {
+    /// The compiler of every pool used on this thread, with a handle that tells if the pool
+    /// still exists.
+    static POOL_COMPILERS: RefCell<HashMap<usize, (Weak<()>, Compiler<'static>)>> =
+        RefCell::new(HashMap::new());
+}
+
+/// A handle to identically configured compilers that can be shared between threads.
+///
+/// Every thread that compiles through the pool lazily creates its own `Compiler`. Once the
+/// pool and all of its clones are dropped, that compiler is freed by the next compile through
+/// any pool on its thread, or when the thread exits.
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone)]
+pub struct CompilerPool {
+    id: usize,
+    config: CompilerConfig,
+    /// Shared by all clones of the pool, the thread-local compilers hold a weak reference.
+    alive: Arc<()>,
+}
+
+#[cfg(feature = "fs")]
+impl CompilerPool {
+    /// Compiles the file at `path` on the calling thread, see `Compiler::compile_from_file`.
+    #[cfg(feature = "fs")]
+    pub fn compile_from_file<T: AsRef<Path>>(
+        &self,
+        path: T,
+        kind: ShaderKind,
+        cache: bool,
+    ) -> Result<Vec<u32>, CompilerError> {
+        self.with_compiler(|compiler| compiler.compile_from_file(path, kind, cache))
+    }
+
+    fn with_compiler<T>(
+        &self,
+        f: impl FnOnce(&mut Compiler<'static>) -> Result<T, CompilerError>,
+    ) -> Result<T, CompilerError> {
+        let compiler = POOL_COMPILERS.with(|compilers| {
+            let mut compilers = compilers.borrow_mut();
+            compilers.retain(|_, (alive, _)| alive.strong_count() > 0);
+            compilers.remove(&self.id).map(|(_, compiler)| compiler)
+        });
+        // The compiler is taken out while in use, a compile through the pool from one of its
+        // callbacks builds a temporary one instead
+        let mut compiler = match compiler {
+            Some(compiler) => compiler,
+            None => self.config.build()?,
+        };
+        let result = f(&mut compiler);
+        POOL_COMPILERS.with(|compilers| {
+            compilers
+                .borrow_mut()
+                .insert(self.id, (Arc::downgrade(&self.alive), compiler));
+        });
+        result
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Drop for CompilerPool {
+    fn drop(&mut self) {
+        // Other threads only notice on their next compile, but this one can free its compiler
+        if Arc::strong_count(&self.alive) == 1 {
+            let _ = POOL_COMPILERS.try_with(|compilers| {
+                if let Ok(mut compilers) = compilers.try_borrow_mut() {
+                    compilers.remove(&self.id);
+                }
+            });
+        }
+    }
+}
+
+/// The configuration of a `Compiler` as plain data.
+///
+/// `shaderc` objects can't be sent to other threads, this can and is used to set up
+/// identically configured compilers on them, see `CompilerBuilder::into_config`.
+#[derive(Debug, Clone)]
+pub struct CompilerConfig {
+    settings: Vec<CompileSetting>,
+    include_dirs: Arc<Mutex<Vec<PathBuf>>>,
+    virtual_includes: Arc<Mutex<HashMap<String, String>>>,
+    max_include_depth: usize,
+    virtual_base_dir: Option<PathBuf>,
+    custom_resolver: Option<CustomIncludeResolver>,
+    cache_strategy: CacheStrategy,
+    cache_dir: Option<PathBuf>,
+    cache_extension: String,
+    spec_constants: HashMap<u32, SpecValue>,
+    #[cfg(feature = "fs")]
+    cache_capacity: Option<usize>,
+    entry_point: String,
+    stage_entry_points: Vec<(ShaderKind, String)>,
+    validate: bool,
+    infer_fallback: bool,
+    collect_warnings: bool,
+    warning_sink: WarningSink,
+    compile_observer: Option<CompileObserver>,
+}
+
+impl CompilerConfig {
+    /// Builds a compiler with fresh `shaderc` options for this configuration.
+    pub fn build<'a>(&self) -> Result<Compiler<'a>, CompilerError> {
+        let mut options = shaderc::CompileOptions::new().ok_or_else(|| {
+            CompilerError::InitError(String::from(
+                "shaderc::CompileOptions::new() returned no options",
+            ))
+        })?;
+        for setting in self.settings.iter() {
+            setting.apply(&mut options);
+        }
+
+        // Each compiler gets its own copy, so adding to one doesn't change the others
+        let mut config = self.clone();
+        config.include_dirs = Arc::new(Mutex::new(self.include_dirs.lock().unwrap().clone()));
+        config.virtual_includes =
+            Arc::new(Mutex::new(self.virtual_includes.lock().unwrap().clone()));
+        Compiler::from_parts(options, config)
+    }
+
+    /// The environment and version set by the last target environment setting, if any.
+    fn target_env(&self) -> Option<(TargetEnv, u32)> {
+        self.settings
+            .iter()
+            .rev()
+            .find_map(|setting| match setting {
+                CompileSetting::TargetEnv(env, version) => Some((*env, *version)),
+                _ => None,
+            })
+    }
+}
+
+pub struct Compiler<'a> {
+    compiler: shaderc::Compiler,
+    options: shaderc::CompileOptions<'a>,
+    #[cfg(feature = "fs")]
+    compile_cache: Arc<Mutex<MemoryCache>>,
+    include_dirs: Arc<Mutex<Vec<PathBuf>>>,
+    virtual_includes: Arc<Mutex<HashMap<String, String>>>,
+    max_include_depth: usize,
+    virtual_base_dir: Option<PathBuf>,
+    custom_resolver: Option<CustomIncludeResolver>,
+    included_files: Arc<Mutex<Vec<PathBuf>>>,
+    include_chain: Arc<Mutex<IncludeChain>>,
+    include_failure: Arc<Mutex<Option<IncludeFailure>>>,
+    cache_strategy: CacheStrategy,
+    cache_dir: Option<PathBuf>,
+    cache_extension: String,
+    spec_constants: HashMap<u32, SpecValue>,
+    settings: Vec<CompileSetting>,
+    entry_point: String,
+    stage_entry_points: Vec<(ShaderKind, String)>,
+    validate: bool,
+    infer_fallback: bool,
+    collect_warnings: bool,
+    warning_sink: WarningSink,
+    compile_observer: Option<CompileObserver>,
+    last_warnings: String,
+    last_compile_source: CompileSource,
+}
+
+impl Debug for Compiler<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Compiler");
+        #[cfg(feature = "fs")]
+        debug.field("compile_cache", &self.compile_cache);
+        debug
+            .field("include_dirs", &self.include_dirs)
+            .field("virtual_includes", &self.virtual_includes)
+            .field("max_include_depth", &self.max_include_depth)
+            .field("virtual_base_dir", &self.virtual_base_dir)
+            .field("custom_resolver", &self.custom_resolver)
+            .field("included_files", &self.included_files)
+            .field("cache_strategy", &self.cache_strategy)
+            .field("cache_dir", &self.cache_dir)
+            .field("cache_extension", &self.cache_extension)
+            .field("spec_constants", &self.spec_constants)
+            .field("entry_point", &self.entry_point)
+            .field("stage_entry_points", &self.stage_entry_points)
+            .field("validate", &self.validate)
+            .field("infer_fallback", &self.infer_fallback)
+            .field("collect_warnings", &self.collect_warnings)
+            .field("warning_sink", &self.warning_sink)
+            .field("compile_observer", &self.compile_observer)
+            .field("last_warnings", &self.last_warnings)
+            .field("last_compile_source", &self.last_compile_source)
+            .finish()
+    }
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new() -> Result<Compiler<'a>, CompilerError> {
+        let options = shaderc::CompileOptions::new().ok_or_else(|| {
+            CompilerError::InitError(String::from(
+                "shaderc::CompileOptions::new() returned no options",
+            ))
+        })?;
+
+        Self::from_parts(
+            options,
+            CompilerConfig {
+                settings: Vec::new(),
+                include_dirs: Arc::new(Mutex::new(Vec::new())),
+                virtual_includes: Arc::new(Mutex::new(HashMap::new())),
+                max_include_depth: DEFAULT_MAX_INCLUDE_DEPTH,
+                virtual_base_dir: None,
+                custom_resolver: None,
+                cache_strategy: CacheStrategy::default(),
+                cache_dir: None,
+                cache_extension: String::from(DEFAULT_CACHE_EXTENSION),
+                spec_constants: HashMap::new(),
+                #[cfg(feature = "fs")]
+                cache_capacity: None,
+                entry_point: String::from("main"),
+                stage_entry_points: Vec::new(),
+                validate: false,
+                infer_fallback: false,
+                collect_warnings: false,
+                warning_sink: WarningSink::default(),
+                compile_observer: None,
+            },
+        )
+    }
+
+    fn from_parts(
+        options: shaderc::CompileOptions<'a>,
+        config: CompilerConfig,
+    ) -> Result<Compiler<'a>, CompilerError> {
+        // Only the last target environment is used, earlier ones were overridden
+        let targets_webgpu = config.target_env() == Some((TargetEnv::Vulkan, WEBGPU));
+        let compiler = shaderc::Compiler::new().ok_or_else(|| {
+            CompilerError::InitError(String::from(
+                "shaderc::Compiler::new() returned no compiler",
+            ))
+        })?;
+
+        let mut compiler = Compiler {
+            compiler,
+            options,
+            #[cfg(feature = "fs")]
+            compile_cache: Arc::new(Mutex::new(MemoryCache::new(config.cache_capacity))),
+            include_dirs: config.include_dirs,
+            virtual_includes: config.virtual_includes,
+            max_include_depth: config.max_include_depth,
+            virtual_base_dir: config.virtual_base_dir,
+            custom_resolver: config.custom_resolver,
+            included_files: Arc::new(Mutex::new(Vec::new())),
+            include_chain: Arc::new(Mutex::new(IncludeChain::default())),
+            include_failure: Arc::new(Mutex::new(None)),
+            cache_strategy: config.cache_strategy,
+            cache_dir: config.cache_dir,
+            cache_extension: config.cache_extension,
+            spec_constants: config.spec_constants,
+            settings: config.settings,
+            entry_point: config.entry_point,
+            stage_entry_points: config.stage_entry_points,
+            validate: config.validate,
+            infer_fallback: config.infer_fallback,
+            collect_warnings: config.collect_warnings,
+            warning_sink: config.warning_sink,
+            compile_observer: config.compile_observer,
+            last_warnings: String::new(),
+            last_compile_source: CompileSource::default(),
+        };
+
+        let include_resolver = compiler.include_resolver();
+        compiler.options.set_include_callback(include_resolver);
+
+        if targets_webgpu {
+            // Rather than silently compiling for another environment, fail if shaderc rejects it
+            compiler
+                .compiler
+                .compile_into_spirv(
+                    "#version 450\nvoid main() {}\n",
+                    ShaderKind::Compute,
+                    "webgpu",
+                    "main",
+                    Some(&compiler.options),
+                )
+                .map_err(|e| {
+                    CompilerError::InitError(format!(
+                        "the linked shaderc does not support targeting WebGPU: {}",
+                        e
+                    ))
+                })?;
+        }
+        Ok(compiler)
+    }
+
+    #[cfg(feature = "fs")]
+    fn config(&self) -> CompilerConfig {
+        CompilerConfig {
+            settings: self.settings.clone(),
+            include_dirs: self.include_dirs.clone(),
+            virtual_includes: self.virtual_includes.clone(),
+            max_include_depth: self.max_include_depth,
+            virtual_base_dir: self.virtual_base_dir.clone(),
+            custom_resolver: self.custom_resolver.clone(),
+            cache_strategy: self.cache_strategy,
+            cache_dir: self.cache_dir.clone(),
+            cache_extension: self.cache_extension.clone(),
+            spec_constants: self.spec_constants.clone(),
+            cache_capacity: self.compile_cache.lock().unwrap().capacity(),
+            entry_point: self.entry_point.clone(),
+            stage_entry_points: self.stage_entry_points.clone(),
+            validate: self.validate,
+            infer_fallback: self.infer_fallback,
+            collect_warnings: self.collect_warnings,
+            warning_sink: self.warning_sink.clone(),
+            compile_observer: self.compile_observer.clone(),
+        }
+    }
+
+    /// Returns the include callback to set on `shaderc::CompileOptions` used by this compiler.
+    fn include_resolver(
+        &self,
+    ) -> impl Fn(&str, shaderc::IncludeType, &str, usize) -> shaderc::IncludeCallbackResult {
+        let include_dirs = self.include_dirs.clone();
+        let virtual_includes = self.virtual_includes.clone();
+        let max_include_depth = self.max_include_depth;
+        let virtual_base_dir = self.virtual_base_dir.clone();
+        let included_files = self.included_files.clone();
+        let include_chain = self.include_chain.clone();
+        let include_failure = self.include_failure.clone();
+        let custom_resolver = self.custom_resolver.clone();
+        move |requested_source, include_type, requesting_source, include_depth| {
+            let virtual_includes = virtual_includes.lock().unwrap();
+            let result = match custom_resolver.as_ref() {
+                Some(_) if include_depth >= max_include_depth => Err(format!(
+                    "Include depth {} too high! ({} included from {})",
+                    include_depth, requested_source, requesting_source
+                )),
+                Some(resolver) => (resolver.0)(
+                    requested_source,
+                    include_type,
+                    requesting_source,
+                    include_depth,
+                ),
+                None => Compiler::resolve_include(
+                    &include_dirs.lock().unwrap(),
+                    virtual_base_dir.as_deref(),
+                    &virtual_includes,
+                    max_include_depth,
+                    &mut include_failure.lock().unwrap(),
+                    requested_source,
+                    include_type,
+                    requesting_source,
+                    include_depth,
+                ),
+            };
+            // shaderc panics on includes with a NUL in them instead of reporting an error
+            let result = result.and_then(|include| {
+                if include.resolved_name.contains('\0') || include.content.contains('\0') {
+                    Err(format!(
+                        "Include {} contains a NUL character (included from {})",
+                        requested_source, requesting_source
+                    ))
+                } else {
+                    Ok(include)
+                }
+            });
+            let result = include_chain.lock().unwrap().check(
+                result,
+                requested_source,
+                include_type,
+                requesting_source,
+                include_depth,
+            );
+
+            // Virtual includes have no file to depend on, nor do names of a custom resolver
+            // that aren't files
+            if let Ok(include) = result.as_ref() {
+                let path = PathBuf::from(include.resolved_name.as_str());
+                let is_file = match custom_resolver {
+                    Some(_) => path.is_file(),
+                    None => !virtual_includes.contains_key(requested_source),
+                };
+                if is_file {
+                    let mut included_files = included_files.lock().unwrap();
+                    if !included_files.contains(&path) {
+                        included_files.push(path);
+                    }
+                }
+            }
+
+            result
+        }
+    }
+
+    /// Runs `include_callback` and records a missing include in `include_failure`.
+    #[allow(clippy::too_many_arguments)]
+    fn resolve_include(
+        include_dirs: &[PathBuf],
+        virtual_base_dir: Option<&Path>,
+        virtual_includes: &HashMap<String, String>,
+        max_include_depth: usize,
+        include_failure: &mut Option<IncludeFailure>,
+        requested_source: &str,
+        include_type: shaderc::IncludeType,
+        requesting_source: &str,
+        include_depth: usize,
+    ) -> Result<shaderc::ResolvedInclude, String> {
+        let result = Compiler::include_callback(
+            include_dirs,
+            virtual_base_dir,
+            virtual_includes,
+            max_include_depth,
+            requested_source,
+            include_type,
+            requesting_source,
+            include_depth,
+        );
+
+        // shaderc retries a failed relative include as a standard one, merge both searches
+        if result.is_ok()
+            && include_failure
+                .as_ref()
+                .is_some_and(|failure| failure.requested == requested_source)
+        {
+            *include_failure = None;
+        }
+        #[cfg(feature = "fs")]
+        if result.is_err() && include_depth < max_include_depth {
+            let searched = Compiler::include_search_dirs(
+                include_dirs,
+                virtual_base_dir,
+                include_type,
+                requesting_source,
+            );
+            if searched
+                .iter()
+                .all(|dir| !dir.join(requested_source).exists())
+            {
+                let failure = include_failure.get_or_insert_with(|| IncludeFailure {
+                    requested: String::from(requested_source),
+                    requested_by: String::from(requesting_source),
+                    searched: Vec::new(),
+                });
+                if failure.requested == requested_source {
+                    for dir in searched {
+                        if !failure.searched.contains(&dir) {
+                            failure.searched.push(dir);
+                        }
+                    }
+                }
+            }
+        }
+        result
     }
 
     pub fn add_macro_definition(&mut self, name: &str, value: Option<&str>) {
-        self.options.add_macro_definition(name, value);
-        self.has_macros = true;
+        let setting = CompileSetting::Macro(String::from(name), value.map(String::from));
+        setting.apply(&mut self.options);
+        self.settings.push(setting);
+        // Binaries compiled before this definition existed are stale now
+        #[cfg(feature = "fs")]
+        self.compile_cache.lock().unwrap().clear();
+    }
+
+    /// Returns a hash of all options that influence the compiled binary.
+    #[cfg(feature = "fs")]
+    fn fingerprint(&self) -> u64 {
+        self.fingerprint_with(true)
+    }
+
+    /// Like `fingerprint`, but leaves out macro definitions if `macros` is false. Macros only
+    /// change a binary through the source they expand in, so a key that also hashes the
+    /// preprocessed source stays valid for shaders that don't use a macro.
+    #[cfg(feature = "fs")]
+    fn fingerprint_with(&self, macros: bool) -> u64 {
+        self.settings
+            .iter()
+            .filter(|setting| macros || !matches!(setting, CompileSetting::Macro(..)))
+            .map(|setting| format!("{:?}", setting))
+            .chain(std::iter::once(self.entry_point.clone()))
+            .fold(FNV_OFFSET_BASIS, |hash, part| {
+                fnv1a(fnv1a(hash, part.as_bytes()), &[0])
+            })
+    }
+
+    /// Sets the name of the entry point function used for subsequent compiles.
+    pub fn set_entry_point(&mut self, name: &str) {
+        self.entry_point = String::from(name);
+        // Binaries of the previous entry point are stale now
+        #[cfg(feature = "fs")]
+        self.compile_cache.lock().unwrap().clear();
+    }
+
+    /// Returns the path of the file the binary of the source file at `path` is cached in.
+    #[cfg(feature = "fs")]
+    fn cache_path(&self, path: &Path) -> PathBuf {
+        match self.cache_dir.as_ref() {
+            Some(cache_dir) => {
+                // Hash the absolute path so same-named files in different dirs don't collide
+                let absolute = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+                let hash = fnv1a(FNV_OFFSET_BASIS, absolute.to_string_lossy().as_bytes());
+                let mut file_name = path.file_name().map(OsString::from).unwrap_or_default();
+                file_name.push(format!(".{:016x}{}", hash, self.cache_extension));
+                cache_dir.join(file_name)
+            }
+            None => sidecar_path(path, &self.cache_extension),
+        }
+    }
+
+    /// Returns the language the source of `path`, or a string source if `None`, compiles as.
+    fn source_language(&self, path: Option<&Path>) -> SourceLanguage {
+        let mut language = SourceLanguage::GLSL;
+        let mut auto = false;
+        for setting in self.settings.iter() {
+            match setting {
+                CompileSetting::SourceLanguage(lang) => language = *lang,
+                CompileSetting::SourceLanguageAuto => auto = true,
+                _ => {}
+            }
+        }
+
+        match path {
+            Some(path) if auto => {
+                let hlsl = path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| {
+                        ext.eq_ignore_ascii_case("hlsl") || ext.eq_ignore_ascii_case("hls")
+                    });
+                if hlsl {
+                    SourceLanguage::HLSL
+                } else {
+                    SourceLanguage::GLSL
+                }
+            }
+            _ => language,
+        }
+    }
+
+    /// Runs `f` with the shaderc compiler, entry point and options for compiling the file at
+    /// `path`. These are the compiler's own options, unless
+    /// `CompilerBuilder::with_source_language_auto` picks a language for the file.
+    #[cfg(feature = "fs")]
+    fn with_file_options<R>(
+        &mut self,
+        path: &Path,
+        f: impl FnOnce(&mut shaderc::Compiler, &str, &shaderc::CompileOptions) -> R,
+    ) -> Result<R, CompilerError> {
+        let options = if self.settings.contains(&CompileSetting::SourceLanguageAuto) {
+            let mut options = self.options.clone().ok_or_else(|| {
+                CompilerError::InitError(String::from(
+                    "shaderc::CompileOptions::clone() returned no options",
+                ))
+            })?;
+            // Cloned options don't keep the include callback
+            options.set_include_callback(self.include_resolver());
+            options.set_source_language(self.source_language(Some(path)));
+            Some(options)
+        } else {
+            None
+        };
+
+        Ok(f(
+            &mut self.compiler,
+            self.entry_point.as_str(),
+            options.as_ref().unwrap_or(&self.options),
+        ))
+    }
+
+    /// Applies the settings that work on the source text, the default version and preambles.
+    /// `path` is the file the source was read from, if any.
+    fn prepare_source<'s>(&self, source: &'s str, path: Option<&Path>) -> Cow<'s, str> {
+        let hlsl = self.source_language(path) == SourceLanguage::HLSL;
+        let mut default_version = None;
+        let mut preamble = String::new();
+        for setting in self.settings.iter() {
+            match setting {
+                CompileSetting::DefaultVersionProfile(version, profile) if !hlsl => {
+                    default_version = Some((*version, *profile))
+                }
+                CompileSetting::Preamble(text) => {
+                    preamble.push_str(text);
+                    if !text.ends_with('\n') {
+                        preamble.push('\n');
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let directive = version_directive(source);
+        let (version, es, injected) = match (&directive, default_version) {
+            (Some(directive), _) => (directive.version, directive.es, String::new()),
+            (None, Some((version, profile))) => {
+                let profile = match profile {
+                    GlslProfile::None => "",
+                    GlslProfile::Core => " core",
+                    GlslProfile::Compatibility => " compatibility",
+                    GlslProfile::Es => " es",
+                };
+                let injected = format!("#version {}{}\n", version, profile);
+                (version, profile == " es", injected)
+            }
+            // Without a directive GLSL defaults to version 110
+            (None, None) => (110, false, String::new()),
+        };
+        if injected.is_empty() && preamble.is_empty() {
+            return Cow::Borrowed(source);
+        }
+
+        // The `#version` directive has to stay in front of the preamble
+        let (head, body) = source.split_at(directive.map_or(0, |directive| directive.end));
+        let next_line = head.matches('\n').count() + 1;
+        // Older versions number the line after `#line n` as n + 1, keep the original numbers
+        let line = if hlsl || version >= 330 || (es && version >= 300) {
+            next_line
+        } else {
+            next_line - 1
+        };
+        let separator = if head.is_empty() || head.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        Cow::Owned(format!(
+            "{}{}{}{}#line {}\n{}",
+            head, separator, injected, preamble, line, body
+        ))
+    }
+
+    /// Returns the key a cached binary of `source` must have been stored with to be valid under
+    /// `CacheStrategy::ContentHash`.
+    #[cfg(feature = "fs")]
+    fn content_cache_key(&mut self, path: &Path, source: &str) -> Result<u64, CompilerError> {
+        let source = self.prepare_source(source, Some(path));
+        self.begin_compile();
+        let preprocessed = self
+            .with_file_options(path, |compiler, entry_point, options| {
+                compiler.preprocess(&source, &path.to_string_lossy(), entry_point, Some(options))
+            })?
+            .map_err(|e| self.compile_error(Some(path), None, e))?;
+        Ok(fnv1a(
+            self.fingerprint_with(false),
+            preprocessed.as_text().as_bytes(),
+        ))
+    }
+
+    /// Drops every binary from the in-memory cache.
+    #[cfg(feature = "fs")]
+    pub fn clear_cache(&mut self) {
+        self.compile_cache.lock().unwrap().clear();
+    }
+
+    /// Drops the binary of `path` from the in-memory cache, returns whether it was cached.
+    #[cfg(feature = "fs")]
+    pub fn remove_from_cache<T: AsRef<Path>>(&mut self, path: T) -> bool {
+        self.compile_cache.lock().unwrap().remove(path.as_ref())
+    }
+
+    /// Returns the binary of `path` from the in-memory cache, without compiling or touching the
+    /// file system. Only needs a shared reference, unlike compiling on a cache miss.
+    #[cfg(feature = "fs")]
+    pub fn get_cached<T: AsRef<Path>>(&self, path: T) -> Option<Vec<u32>> {
+        self.compile_cache
+            .lock()
+            .unwrap()
+            .get(path.as_ref())
+            .map(|binary| binary.to_vec())
+    }
+
+    /// Returns whether `compile_from_file` with `cache` set would run shaderc for `path`,
+    /// without compiling anything. Only file metadata and the header at the start of the cache
+    /// file are read, a corrupt binary is only noticed when it is loaded. With
+    /// `CacheStrategy::ContentHash` only binaries in the in-memory cache are known to be up to
+    /// date, as the key requires preprocessing the source.
+    #[cfg(feature = "fs")]
+    pub fn would_recompile<T: AsRef<Path>>(&self, path: T) -> bool {
+        let path = path.as_ref();
+        if self.compile_cache.lock().unwrap().contains(path) {
+            return false;
+        }
+        if self.cache_strategy == CacheStrategy::ContentHash {
+            return true;
+        }
+
+        let precompiled = self.cache_path(path);
+        let modified = |path: &Path| path.metadata().and_then(|meta| meta.modified()).ok();
+        match (modified(path), modified(&precompiled)) {
+            (Some(source), Some(cached)) if source < cached => {}
+            _ => return true,
+        }
+
+        let mut header = [0; cache::SIDECAR_HEADER_WORDS * 4];
+        if File::open(&precompiled)
+            .and_then(|mut file| file.read_exact(&mut header))
+            .is_err()
+        {
+            return true;
+        }
+        let header: Vec<u32> = header
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        !cache::sidecar_header_matches(&header, self.fingerprint())
+    }
+
+    /// Returns the number of binaries in the in-memory cache.
+    #[cfg(feature = "fs")]
+    pub fn cache_len(&self) -> usize {
+        self.compile_cache.lock().unwrap().len()
+    }
+
+    /// Writes the in-memory cache to `path`, so a later run can restore it with `load_cache`.
+    /// Entries with a path that is not valid UTF-8 are skipped.
+    #[cfg(feature = "fs")]
+    pub fn save_cache<T: AsRef<Path>>(&self, path: T) -> Result<(), CompilerError> {
+        let saved_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64);
+        let bytes = self
+            .compile_cache
+            .lock()
+            .unwrap()
+            .to_bytes(self.fingerprint(), saved_at);
+        Self::write_cache_file(path.as_ref(), &bytes)
+    }
+
+    /// Restores a cache written by `save_cache` into the in-memory cache.
+    ///
+    /// Nothing is restored if the cache was saved by a compiler with different options, and
+    /// entries of which the source was modified after saving, or no longer exists, are
+    /// discarded.
+    #[cfg(feature = "fs")]
+    pub fn load_cache<T: AsRef<Path>>(&mut self, path: T) -> Result<(), CompilerError> {
+        let bytes =
+            std::fs::read(path.as_ref()).map_err(|e| CompilerError::LoadError(Arc::new(e)))?;
+        let saved = SavedCache::from_bytes(&bytes).map_err(|e| {
+            CompilerError::LoadError(Arc::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.as_ref().display(), e),
+            )))
+        })?;
+
+        if saved.fingerprint != self.fingerprint() {
+            return Ok(());
+        }
+
+        let mut compile_cache = self.compile_cache.lock().unwrap();
+        for (source, binary) in saved.entries {
+            let modified = source
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok());
+            if let Some(modified) = modified {
+                if modified.as_nanos() <= u128::from(saved.saved_at) {
+                    compile_cache.insert(source, binary);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the specialization constants set through `CompilerBuilder::with_spec_constant`.
+    ///
+    /// These are only recorded for use when creating pipelines, the compiled SPIR-V itself is
+    /// not modified.
+    pub fn spec_constants(&self) -> &HashMap<u32, SpecValue> {
+        &self.spec_constants
+    }
+
+    /// Returns the include directories, in search order.
+    #[cfg(feature = "fs")]
+    pub fn include_dirs(&self) -> Vec<PathBuf> {
+        self.include_dirs.lock().unwrap().clone()
+    }
+
+    /// Adds an include directory to search after the existing ones, like
+    /// `CompilerBuilder::with_include_dir`. Only this compiler searches the new directory, not
+    /// other compilers built from the same `CompilerConfig`.
+    #[cfg(feature = "fs")]
+    pub fn add_include_dir<T: AsRef<Path>>(&self, path: T) {
+        push_include_dir(&mut self.include_dirs.lock().unwrap(), path.as_ref());
     }
 
-    pub(crate) fn include_callback(
-        include_dirs: &[PathBuf],
-        requested_source: &str,
-        include_type: shaderc::IncludeType,
-        requesting_source: &str,
-        include_depth: usize,
-    ) -> Result<shaderc::ResolvedInclude, String> {
-        use shaderc::{IncludeType, ResolvedInclude};
-        if include_depth >= 32 {
-            return Err(format!(
-                "Include depth {} too high!",
-                include_depth
-            ));
-        }
+    /// Returns every file included by the last compilation that ran shaderc.
+    #[cfg(feature = "fs")]
+    pub fn last_includes(&self) -> Vec<PathBuf> {
+        self.included_files.lock().unwrap().clone()
+    }
+
+    /// Writes a Make-style depfile to `output` that lists `deps` as the prerequisites of
+    /// `target`, for build systems like Ninja. Pass the source file and `last_includes` to
+    /// rebuild `target` whenever one of them changes.
+    #[cfg(feature = "fs")]
+    pub fn write_depfile(
+        &self,
+        output: &Path,
+        target: &str,
+        deps: &[PathBuf],
+    ) -> Result<(), CompilerError> {
+        let mut depfile = escape_make_path(target);
+        depfile.push(':');
+        for dep in deps {
+            depfile.push_str(" \\\n  ");
+            depfile.push_str(&escape_make_path(&dep.to_string_lossy()));
+        }
+        depfile.push('\n');
+        Self::write_cache_file(output, depfile.as_bytes())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(not(feature = "fs"), allow(unused_variables))]
+    pub(crate) fn include_callback(
+        include_dirs: &[PathBuf],
+        virtual_base_dir: Option<&Path>,
+        virtual_includes: &HashMap<String, String>,
+        max_include_depth: usize,
+        requested_source: &str,
+        include_type: shaderc::IncludeType,
+        requesting_source: &str,
+        include_depth: usize,
+    ) -> Result<shaderc::ResolvedInclude, String> {
+        use shaderc::ResolvedInclude;
+        // Checked before any lookup, so relative and standard includes share the same limit
+        if include_depth >= max_include_depth {
+            return Err(format!(
+                "Include depth {} too high! ({} included from {})",
+                include_depth, requested_source, requesting_source
+            ));
+        }
+
+        if let Some(content) = virtual_includes.get(requested_source) {
+            return Ok(ResolvedInclude {
+                resolved_name: String::from(requested_source),
+                content: content.clone(),
+            });
+        }
+
+        #[cfg(feature = "fs")]
+        for dir in Self::include_search_dirs(
+            include_dirs,
+            virtual_base_dir,
+            include_type,
+            requesting_source,
+        ) {
+            let final_path = dir.join(requested_source);
+            if final_path.exists() {
+                if let Ok(file) = File::open(&final_path) {
+                    return Self::read_include(&final_path, file);
+                }
+            }
+        }
+
+        Err(format!(
+            "Could not find file: {} (included from {})",
+            requested_source, requesting_source
+        ))
+    }
+
+    /// Returns the directories searched for an include, in order. `#include "file"` first
+    /// searches the directory of the requesting source, which lies in `virtual_base_dir` for
+    /// sources that aren't files.
+    #[cfg(feature = "fs")]
+    fn include_search_dirs(
+        include_dirs: &[PathBuf],
+        virtual_base_dir: Option<&Path>,
+        include_type: shaderc::IncludeType,
+        requesting_source: &str,
+    ) -> Vec<PathBuf> {
+        let mut dirs = Vec::with_capacity(include_dirs.len() + 1);
+        if include_type == shaderc::IncludeType::Relative {
+            let requesting_source = Path::new(requesting_source);
+            let base_folder = requesting_source.parent().unwrap_or_else(|| Path::new(""));
+            match virtual_base_dir {
+                Some(base_dir) if !requesting_source.is_file() => {
+                    dirs.push(base_dir.join(base_folder))
+                }
+                _ => dirs.push(base_folder.to_path_buf()),
+            }
+        }
+        dirs.extend_from_slice(include_dirs);
+        dirs
+    }
+
+    /// Reads an include from disk. shaderc reports messages in the include with its resolved
+    /// name and its own line numbers, so this is the path the include was found at.
+    #[cfg(feature = "fs")]
+    fn read_include(path: &Path, mut file: File) -> Result<shaderc::ResolvedInclude, String> {
+        let resolved_name = path
+            .to_str()
+            .ok_or_else(|| format!("Include path is not valid UTF-8: {}", path.display()))?;
+
+        let mut source = String::new();
+        if let Err(e) = file.read_to_string(&mut source) {
+            return Err(format!("Could not read file: {}, {}", path.display(), e));
+        }
+
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: String::from(resolved_name),
+            content: source,
+        })
+    }
+
+    pub fn compile_from_string(
+        &mut self,
+        source: &str,
+        kind: shaderc::ShaderKind,
+    ) -> Result<Vec<u32>, CompilerError> {
+        let start = Instant::now();
+        let output = self.compile_from_string_with_output(source, kind)?;
+        self.observe(start, kind, &output.binary);
+        Ok(self.report_warnings("memory", output))
+    }
+
+    /// Like `compile_from_string`, but also returns the warnings shaderc produced.
+    pub fn compile_from_string_with_output(
+        &mut self,
+        source: &str,
+        kind: shaderc::ShaderKind,
+    ) -> Result<CompileOutput, CompilerError> {
+        self.compile_named_source(source, kind, "memory", &[])
+    }
+
+    /// Like `compile_from_string`, but reports the source as `name` instead of `"memory"`
+    /// in messages.
+    pub fn compile_from_named_string(
+        &mut self,
+        source: &str,
+        kind: shaderc::ShaderKind,
+        name: &str,
+    ) -> Result<Vec<u32>, CompilerError> {
+        let output = self.compile_named_source(source, kind, name, &[])?;
+        Ok(self.report_warnings(name, output))
+    }
+
+    /// Like `compile_from_named_string`, for a source that still has to be checked for being
+    /// valid UTF-8. Invalid sources, like sources containing a NUL character, fail with a
+    /// `LoadError` of kind `InvalidData`.
+    pub fn compile_from_bytes(
+        &mut self,
+        bytes: &[u8],
+        kind: shaderc::ShaderKind,
+        name: &str,
+    ) -> Result<Vec<u32>, CompilerError> {
+        let source = std::str::from_utf8(bytes).map_err(|e| {
+            CompilerError::LoadError(Arc::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not valid UTF-8: {}", name, e),
+            )))
+        })?;
+        self.compile_from_named_string(source, kind, name)
+    }
+
+    /// Compiles `source` with the stage declared by a `#pragma shader_stage(<stage>)` directive
+    /// in it. Sources without the pragma fail with a `CompilerError::Log` saying it is required.
+    pub fn compile_infer_from_source(
+        &mut self,
+        source: &str,
+        name: &str,
+    ) -> Result<Vec<u32>, CompilerError> {
+        let output = self.compile_named_source(source, ShaderKind::InferFromSource, name, &[])?;
+        Ok(self.report_warnings(name, output))
+    }
+
+    /// Like `compile_from_string`, but returns the binary as little-endian bytes.
+    pub fn compile_from_string_bytes(
+        &mut self,
+        source: &str,
+        kind: shaderc::ShaderKind,
+    ) -> Result<Vec<u8>, CompilerError> {
+        self.compile_from_string(source, kind)
+            .map(|binary| words_to_bytes(&binary))
+    }
+
+    /// Reads the source from `reader` and compiles it, `name` is the source name shaderc
+    /// reports in messages. A source containing a NUL character fails with a `LoadError` of
+    /// kind `InvalidData`.
+    pub fn compile_from_reader<R: Read>(
+        &mut self,
+        mut reader: R,
+        kind: shaderc::ShaderKind,
+        name: &str,
+    ) -> Result<Vec<u32>, CompilerError> {
+        let mut source = String::new();
+        if let Err(e) = reader.read_to_string(&mut source) {
+            return Err(CompilerError::LoadError(Arc::new(io::Error::new(
+                e.kind(),
+                format!("{}: {}", name, e),
+            ))));
+        }
+
+        let output = self.compile_named_source(source.as_str(), kind, name, &[])?;
+        Ok(self.report_warnings(name, output))
+    }
+
+    /// Like `compile_from_string`, but defines `macros` for this compile only, on top of the
+    /// macros the compiler was built with.
+    pub fn compile_from_string_with_macros(
+        &mut self,
+        source: &str,
+        kind: shaderc::ShaderKind,
+        macros: &[(&str, Option<&str>)],
+    ) -> Result<Vec<u32>, CompilerError> {
+        let macros: Vec<CompileSetting> = macros
+            .iter()
+            .map(|(name, value)| {
+                CompileSetting::Macro(String::from(*name), value.map(String::from))
+            })
+            .collect();
+        let output = self.compile_named_source(source, kind, "memory", &macros)?;
+        Ok(self.report_warnings("memory", output))
+    }
+
+    /// Compiles `source` once for every name in `entry_points` and returns the binaries by
+    /// entry point. shaderc compiles a single entry point at a time, so this is the same as
+    /// calling `compile_from_string` with each entry point set.
+    pub fn compile_entry_points(
+        &mut self,
+        source: &str,
+        kind: shaderc::ShaderKind,
+        entry_points: &[&str],
+    ) -> Result<HashMap<String, Vec<u32>>, CompilerError> {
+        let entry_point = self.entry_point.clone();
+        let mut binaries = HashMap::with_capacity(entry_points.len());
+        let mut result = Ok(());
+        for name in entry_points {
+            self.entry_point = String::from(*name);
+            match self.compile_named_source(source, kind, "memory", &[]) {
+                Ok(output) => {
+                    binaries.insert(String::from(*name), self.report_warnings("memory", output));
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.entry_point = entry_point;
+        result.map(|_| binaries)
+    }
+
+    /// Like `compile_from_string`, but targets `env` with `version` for this compile only,
+    /// for example to compile the same source for both Vulkan and OpenGL.
+    pub fn compile_from_string_for_env(
+        &mut self,
+        source: &str,
+        kind: shaderc::ShaderKind,
+        env: TargetEnv,
+        version: u32,
+    ) -> Result<Vec<u32>, CompilerError> {
+        let output = self.compile_named_source(
+            source,
+            kind,
+            "memory",
+            &[CompileSetting::TargetEnv(env, version)],
+        )?;
+        Ok(self.report_warnings("memory", output))
+    }
+
+    fn compile_named_source(
+        &mut self,
+        source: &str,
+        kind: shaderc::ShaderKind,
+        name: &str,
+        overrides: &[CompileSetting],
+    ) -> Result<CompileOutput, CompilerError> {
+        reject_nul(source, name)?;
+        let source = self.prepare_source(source, None);
+        self.begin_compile();
+        self.last_warnings.clear();
+
+        let binary_result = if overrides.is_empty() {
+            self.compiler.compile_into_spirv(
+                &source,
+                kind,
+                name,
+                self.entry_point.as_str(),
+                Some(&self.options),
+            )
+        } else {
+            let mut options = self.options.clone().ok_or_else(|| {
+                CompilerError::InitError(String::from(
+                    "shaderc::CompileOptions::clone() returned no options",
+                ))
+            })?;
+            // Cloned options don't keep the include callback
+            options.set_include_callback(self.include_resolver());
+            for setting in overrides {
+                setting.apply(&mut options);
+            }
+            self.compiler.compile_into_spirv(
+                &source,
+                kind,
+                name,
+                self.entry_point.as_str(),
+                Some(&options),
+            )
+        };
+
+        match binary_result {
+            Err(e) => Err(self.compile_error(None, Some(kind), e)),
+            Ok(result) => self.validated(CompileOutput::from(&result)),
+        }
+    }
+
+    /// Resets the state that the include callback collects during a compile.
+    fn begin_compile(&mut self) {
+        self.last_compile_source = CompileSource::Compiled;
+        self.included_files.lock().unwrap().clear();
+        *self.include_failure.lock().unwrap() = None;
+    }
+
+    /// Converts an error of shaderc, a missing include is reported as
+    /// `CompilerError::IncludeNotFound` instead of by its message. `kind` is `None` for errors
+    /// of the preprocessor.
+    fn compile_error(
+        &self,
+        file: Option<&Path>,
+        kind: Option<ShaderKind>,
+        error: shaderc::Error,
+    ) -> CompilerError {
+        if let Some(failure) = self.include_failure.lock().unwrap().take() {
+            return CompilerError::IncludeNotFound {
+                requested: failure.requested,
+                requested_by: failure.requested_by,
+                searched: failure.searched,
+            };
+        }
+
+        CompilationError {
+            file: file.map(error_path),
+            kind,
+            entry_point: kind.map(|_| self.entry_point.clone()),
+            description: error.to_string(),
+        }
+        .into()
+    }
+
+    /// Post-processes a binary compiled by shaderc, validating it if enabled.
+    fn validated(&mut self, mut output: CompileOutput) -> Result<CompileOutput, CompilerError> {
+        if self.settings.contains(&CompileSetting::StripDebugSource) {
+            output.binary = spirv::strip_source(&output.binary);
+        }
+        if self.validate {
+            spirv::validate(&output.binary).map_err(CompilerError::Validation)?;
+        }
+        self.last_warnings = output.warnings.clone();
+        Ok(output)
+    }
+
+    /// Returns the warnings of the last compile that ran shaderc and clears them. Empty if it
+    /// failed or produced no warnings.
+    pub fn take_last_warnings(&mut self) -> String {
+        std::mem::take(&mut self.last_warnings)
+    }
+
+    /// Returns whether the binary of the last compile was served from a cache or compiled.
+    pub fn last_compile_source(&self) -> CompileSource {
+        self.last_compile_source
+    }
+
+    pub fn last_was_cache_hit(&self) -> bool {
+        self.last_compile_source != CompileSource::Compiled
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn compile_from_file<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+        kind: shaderc::ShaderKind,
+        cache: bool,
+    ) -> Result<Vec<u32>, CompilerError> {
+        let start = Instant::now();
+        let output = self.compile_from_file_with_output(path.as_ref(), kind, cache)?;
+        self.observe(start, kind, &output.binary);
+        Ok(self.report_warnings(&path.as_ref().display().to_string(), output))
+    }
+
+    /// Like `compile_from_file`, but returns a binary that is shared with the in-memory cache,
+    /// so a cache hit only bumps a reference count instead of copying the binary.
+    #[cfg(feature = "fs")]
+    pub fn compile_from_file_shared<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+        kind: shaderc::ShaderKind,
+        cache: bool,
+    ) -> Result<Arc<[u32]>, CompilerError> {
+        let path = path.as_ref();
+        if cache {
+            let start = Instant::now();
+            let cached = self.compile_cache.lock().unwrap().get(path).cloned();
+            if let Some(binary) = cached {
+                self.last_compile_source = CompileSource::InMemoryCache;
+                self.observe(start, kind, &binary);
+                return Ok(binary);
+            }
+        }
+
+        let binary = self.compile_from_file(path, kind, cache)?;
+        // Share the copy the compile stored in the in-memory cache, unless it was evicted
+        let cached = self.compile_cache.lock().unwrap().get(path).cloned();
+        Ok(cached.unwrap_or_else(|| Arc::from(binary)))
+    }
+
+    /// Passes the stats of a compile that started at `start` to the compile observer.
+    fn observe(&self, start: Instant, kind: ShaderKind, binary: &[u32]) {
+        if let Some(observer) = self.compile_observer.as_ref() {
+            (observer.0)(&CompileStats {
+                duration: start.elapsed(),
+                word_count: binary.len(),
+                kind,
+                cache_hit: self.last_was_cache_hit(),
+            });
+        }
+    }
+
+    /// Reports the warnings of `output` to the warning sink, unless they are collected for
+    /// `take_last_warnings`.
+    fn report_warnings(&self, name: &str, output: CompileOutput) -> Vec<u32> {
+        if output.warning_count > 0 && !self.collect_warnings {
+            self.warning_sink.report(&format!(
+                "File {} produced {} warnings: {}",
+                name, output.warning_count, output.warnings
+            ));
+        }
+        output.binary
+    }
+
+    /// Like `compile_from_file`, but returns the binary as little-endian bytes.
+    #[cfg(feature = "fs")]
+    pub fn compile_from_file_bytes<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+        kind: shaderc::ShaderKind,
+        cache: bool,
+    ) -> Result<Vec<u8>, CompilerError> {
+        self.compile_from_file(path, kind, cache)
+            .map(|binary| words_to_bytes(&binary))
+    }
+
+    /// Compiles the file at `src` and writes the binary as little-endian bytes to `dst`,
+    /// creating its parent directories if needed. This bypasses the caches.
+    #[cfg(feature = "fs")]
+    pub fn compile_to_file<S: AsRef<Path>, D: AsRef<Path>>(
+        &mut self,
+        src: S,
+        kind: shaderc::ShaderKind,
+        dst: D,
+    ) -> Result<(), CompilerError> {
+        let binary = self.compile_from_file(src, kind, false)?;
+        if let Some(parent) = dst.as_ref().parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                return Err(CompilerError::WriteError(Arc::new(e)));
+            }
+        }
+        Self::write_cache_file(dst.as_ref(), &words_to_bytes(&binary))
+    }
+
+    /// Like `compile_from_file`, but returns the warnings shaderc produced instead of
+    /// printing them. Binaries served from a cache report no warnings.
+    #[cfg(feature = "fs")]
+    pub fn compile_from_file_with_output<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+        kind: shaderc::ShaderKind,
+        cache: bool,
+    ) -> Result<CompileOutput, CompilerError> {
+        self.compile_file(path.as_ref(), kind, cache, cache)
+    }
+
+    /// Compiles the file at `path` with shaderc, ignoring any cached binary, and then updates
+    /// both the in-memory cache and the cache file with the result.
+    #[cfg(feature = "fs")]
+    pub fn recompile_from_file<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+        kind: shaderc::ShaderKind,
+    ) -> Result<Vec<u32>, CompilerError> {
+        let output = self.compile_file(path.as_ref(), kind, false, true)?;
+        Ok(self.report_warnings(&path.as_ref().display().to_string(), output))
+    }
+
+    /// Compiles the file at `path`, serving it from a cache if `use_cached` is set and writing
+    /// the binary to its cache file if `cache` is set.
+    #[cfg(feature = "fs")]
+    fn compile_file(
+        &mut self,
+        path: &Path,
+        kind: shaderc::ShaderKind,
+        use_cached: bool,
+        cache: bool,
+    ) -> Result<CompileOutput, CompilerError> {
+        let precompiled = self.cache_path(path);
+
+        if use_cached {
+            if let Some(binary) = self.get_cached(path) {
+                self.last_compile_source = CompileSource::InMemoryCache;
+                return Ok(CompileOutput::from(binary));
+            }
+        }
+
+        // Under `Mtime` an up to date cache file is loaded without reading the source
+        let mut source = None;
+        let cache_key = match self.cache_strategy {
+            _ if !cache => None,
+            CacheStrategy::Mtime => Some(self.fingerprint()),
+            CacheStrategy::ContentHash => {
+                let text = Self::read_source(path)?;
+                let key = self.content_cache_key(path, text.as_str())?;
+                source = Some(text);
+                Some(key)
+            }
+        };
+
+        if let Some(cache_key) = cache_key.filter(|_| use_cached) {
+            if precompiled.exists() {
+                let should_recompile: bool = match self.cache_strategy {
+                    CacheStrategy::Mtime => {
+                        if let (Ok(meta_data), Ok(pre_meta_data)) =
+                            (path.metadata(), precompiled.metadata())
+                        {
+                            let source_last_modified = meta_data.modified();
+                            let last_modified = pre_meta_data.modified();
+                            if let (Ok(source_last_modified), Ok(last_modified)) =
+                                (source_last_modified, last_modified)
+                            {
+                                source_last_modified.cmp(&last_modified) == Ordering::Less
+                            } else {
+                                true
+                            }
+                        } else {
+                            // Reading the source below reports why it has no metadata
+                            false
+                        }
+                    }
+                    // The cache key covers the content, so timestamps don't matter
+                    CacheStrategy::ContentHash => true,
+                };
+
+                // Only load pre-compiled files if they are up to date
+                if should_recompile {
+                    // A cache file that can't be read is treated as missing, one that isn't
+                    // made of whole words was truncated and is recompiled
+                    if let Some(bytes) = std::fs::read(&precompiled)
+                        .ok()
+                        .filter(|bytes| bytes.len() % 4 == 0)
+                    {
+                        let words: Vec<u32> = bytes
+                            .chunks_exact(4)
+                            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+                            .collect();
+
+                        // A header with another cache key means the binary is stale, a
+                        // checksum mismatch that it is corrupt. Both are recompiled
+                        if let Some(binary) = cache::from_sidecar(&words, cache_key) {
+                            let bytes = binary.to_vec();
+                            self.compile_cache
+                                .lock()
+                                .unwrap()
+                                .insert(path.to_path_buf(), bytes.clone());
+                            self.last_compile_source = CompileSource::DiskCache;
+                            return Ok(CompileOutput::from(bytes));
+                        }
+                    }
+                }
+            }
+        }
+
+        let source = match source {
+            Some(source) => source,
+            None => Self::read_source(path)?,
+        };
+        let source = self.prepare_source(source.as_str(), Some(path));
+        self.begin_compile();
+        self.last_warnings.clear();
+        let binary_result = self.with_file_options(path, |compiler, entry_point, options| {
+            compiler.compile_into_spirv(
+                &source,
+                kind,
+                &path.to_string_lossy(),
+                entry_point,
+                Some(options),
+            )
+        })?;
+
+        if let Err(e) = binary_result {
+            return Err(self.compile_error(Some(path), Some(kind), e));
+        }
+
+        let output = self.validated(CompileOutput::from(&binary_result.unwrap()))?;
+        let bytes = &output.binary;
+
+        if let Some(cache_key) = cache_key {
+            if let Some(cache_dir) = self.cache_dir.as_ref() {
+                if let Err(e) = std::fs::create_dir_all(cache_dir) {
+                    return Err(CompilerError::WriteError(Arc::new(e)));
+                }
+            }
+
+            let words = cache::to_sidecar(cache_key, bytes);
+            Self::write_cache_file(&precompiled, &words_to_bytes(&words))?;
+        }
+
+        self.compile_cache
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), bytes.clone());
+        Ok(output)
+    }
+
+    /// Compiles every file in `files`, a failing file does not stop the others from compiling.
+    #[cfg(feature = "fs")]
+    pub fn compile_many<T: AsRef<Path>>(
+        &mut self,
+        files: &[(T, ShaderKind)],
+        cache: bool,
+    ) -> BatchResult {
+        files
+            .iter()
+            .map(|(path, kind)| {
+                (
+                    path.as_ref().to_path_buf(),
+                    self.compile_from_file(path, *kind, cache),
+                )
+            })
+            .collect()
+    }
+
+    /// Compiles every file in `dir` with a recognized shader extension, see
+    /// `compile_from_file_auto`. Files are compiled in path order, a directory that can't be
+    /// read is reported as a `LoadError` entry.
+    #[cfg(feature = "fs")]
+    pub fn compile_dir<T: AsRef<Path>>(
+        &mut self,
+        dir: T,
+        recursive: bool,
+        cache: bool,
+    ) -> Vec<(PathBuf, Result<Vec<u32>, CompilerError>)> {
+        let mut results = Vec::new();
+        let mut files = Vec::new();
+        Self::collect_shader_files(dir.as_ref(), recursive, &mut files, &mut results);
+        files.sort();
+
+        for file in files {
+            let result = self.compile_from_file_auto(&file, cache);
+            results.push((file, result));
+        }
+        results
+    }
+
+    #[cfg(feature = "fs")]
+    fn collect_shader_files(
+        dir: &Path,
+        recursive: bool,
+        files: &mut Vec<PathBuf>,
+        errors: &mut Vec<(PathBuf, Result<Vec<u32>, CompilerError>)>,
+    ) {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                errors.push((
+                    dir.to_path_buf(),
+                    Err(CompilerError::LoadError(Arc::new(e))),
+                ));
+                return;
+            }
+        };
+
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    errors.push((
+                        dir.to_path_buf(),
+                        Err(CompilerError::LoadError(Arc::new(e))),
+                    ));
+                    continue;
+                }
+            };
+
+            if path.is_dir() {
+                if recursive {
+                    Self::collect_shader_files(&path, recursive, files, errors);
+                }
+            } else if shader_kind_from_path(&path).is_some() {
+                files.push(path);
+            }
+        }
+    }
+
+    /// Compiles every job on a pool of worker threads, each with its own identically
+    /// configured compiler. Results are returned in the same order as `jobs`.
+    ///
+    /// The in-memory and file caches are not used.
+    #[cfg(feature = "fs")]
+    pub fn compile_parallel(
+        &self,
+        jobs: &[(PathBuf, ShaderKind)],
+    ) -> Vec<Result<Vec<u32>, CompilerError>> {
+        let config = self.config();
+        let next_job = AtomicUsize::new(0);
+        let results: Vec<Mutex<Option<_>>> = jobs.iter().map(|_| Mutex::new(None)).collect();
+
+        let workers = std::thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1)
+            .min(jobs.len());
+
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    let mut compiler = config.build();
+                    loop {
+                        let index = next_job.fetch_add(1, AtomicOrdering::Relaxed);
+                        if index >= jobs.len() {
+                            break;
+                        }
+
+                        let (path, kind) = &jobs[index];
+                        let result = match compiler.as_mut() {
+                            Ok(compiler) => compiler.compile_from_file(path, *kind, false),
+                            Err(e) => Err(e.clone()),
+                        };
+                        *results[index].lock().unwrap() = Some(result);
+                    }
+                });
+            }
+        });
+
+        results
+            .into_iter()
+            .map(|result| result.into_inner().unwrap().unwrap())
+            .collect()
+    }
+
+    #[cfg(feature = "fs")]
+    fn read_source(path: &Path) -> Result<String, CompilerError> {
+        let file = File::open(path);
+        if let Err(e) = file {
+            return Err(CompilerError::LoadError(Arc::new(e)));
+        }
+
+        let mut file = file.unwrap();
+        let mut source = String::new();
+        if let Err(e) = file.read_to_string(&mut source) {
+            let message = if e.kind() == io::ErrorKind::InvalidData {
+                format!("{} is not valid UTF-8", path.display())
+            } else {
+                format!("{}: {}", path.display(), e)
+            };
+            return Err(CompilerError::LoadError(Arc::new(io::Error::new(
+                e.kind(),
+                message,
+            ))));
+        }
+        reject_nul(&source, &path.display().to_string())?;
+        Ok(source)
+    }
+
+    /// Compiles `source` into human-readable SPIR-V assembly.
+    pub fn compile_to_assembly_from_string(
+        &mut self,
+        source: &str,
+        kind: shaderc::ShaderKind,
+    ) -> Result<String, CompilerError> {
+        reject_nul(source, "memory")?;
+        let source = self.prepare_source(source, None);
+        self.begin_compile();
+        self.compiler
+            .compile_into_spirv_assembly(
+                &source,
+                kind,
+                "memory",
+                self.entry_point.as_str(),
+                Some(&self.options),
+            )
+            .map(|result| result.as_text())
+            .map_err(|e| self.compile_error(None, Some(kind), e))
+    }
+
+    /// Compiles the file at `path` into human-readable SPIR-V assembly.
+    #[cfg(feature = "fs")]
+    pub fn compile_to_assembly_from_file<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+        kind: shaderc::ShaderKind,
+    ) -> Result<String, CompilerError> {
+        let source = Self::read_source(path.as_ref())?;
+        let source = self.prepare_source(source.as_str(), Some(path.as_ref()));
+        self.begin_compile();
+        self.with_file_options(path.as_ref(), |compiler, entry_point, options| {
+            compiler.compile_into_spirv_assembly(
+                &source,
+                kind,
+                &path.as_ref().to_string_lossy(),
+                entry_point,
+                Some(options),
+            )
+        })?
+        .map(|result| result.as_text())
+        .map_err(|e| self.compile_error(Some(path.as_ref()), Some(kind), e))
+    }
+
+    /// Assembles SPIR-V assembly text, such as produced by `compile_to_assembly_from_string`,
+    /// into a binary for the configured target environment.
+    pub fn assemble_from_string(&mut self, asm: &str) -> Result<Vec<u32>, CompilerError> {
+        reject_nul(asm, "memory")?;
+        self.begin_compile();
+        let result = self
+            .compiler
+            .assemble(asm, Some(&self.options))
+            .map_err(|e| self.compile_error(None, None, e))?;
+        Ok(self.validated(CompileOutput::from(&result))?.binary)
+    }
+
+    /// Optimizes an already compiled `binary`, such as a prebuilt `.spv` file, for `level`.
+    ///
+    /// shaderc only runs its SPIR-V optimizer while compiling source and can't disassemble
+    /// binaries, so only the passes this crate implements are available: `Size` and
+    /// `Performance` strip debug instructions like `strip_debug`, `Zero` keeps the binary as is.
+    /// Fails with `CompilerError::Validation` if `binary` is not a well formed module.
+    pub fn optimize_spirv(
+        &mut self,
+        binary: &[u32],
+        level: OptimizationLevel,
+    ) -> Result<Vec<u32>, CompilerError> {
+        match level {
+            OptimizationLevel::Zero => {
+                spirv::validate(binary).map_err(CompilerError::Validation)?;
+                Ok(binary.to_vec())
+            }
+            OptimizationLevel::Size | OptimizationLevel::Performance => strip_debug(binary),
+        }
+    }
+
+    /// Runs only the preprocessor on `source`, expanding macros and includes.
+    ///
+    /// Unlike the compile methods this takes no shader kind, as shaderc preprocesses
+    /// every stage the same way.
+    pub fn preprocess_from_string(&mut self, source: &str) -> Result<String, CompilerError> {
+        reject_nul(source, "memory")?;
+        let source = self.prepare_source(source, None);
+        self.begin_compile();
+        self.compiler
+            .preprocess(
+                &source,
+                "memory",
+                self.entry_point.as_str(),
+                Some(&self.options),
+            )
+            .map(|result| result.as_text())
+            .map_err(|e| self.compile_error(None, None, e))
+    }
+
+    /// Runs only the preprocessor on the file at `path`, expanding macros and includes.
+    #[cfg(feature = "fs")]
+    pub fn preprocess_from_file<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+    ) -> Result<String, CompilerError> {
+        let source = Self::read_source(path.as_ref())?;
+        let source = self.prepare_source(source.as_str(), Some(path.as_ref()));
+        self.begin_compile();
+        self.with_file_options(path.as_ref(), |compiler, entry_point, options| {
+            compiler.preprocess(
+                &source,
+                &path.as_ref().to_string_lossy(),
+                entry_point,
+                Some(options),
+            )
+        })?
+        .map(|result| result.as_text())
+        .map_err(|e| self.compile_error(Some(path.as_ref()), None, e))
+    }
+
+    /// Writes `words` to `path` through a temporary file, so a process killed while writing
+    /// can never leave a truncated cache file behind.
+    ///
+    /// If `path` already holds `bytes` only its modification time is updated, which keeps it
+    /// up to date for `CacheStrategy::Mtime` without rewriting it.
+    #[cfg(feature = "fs")]
+    fn write_cache_file(path: &Path, bytes: &[u8]) -> Result<(), CompilerError> {
+        static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        if std::fs::read(path).is_ok_and(|existing| existing == bytes) {
+            if let Err(e) = File::options()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_modified(SystemTime::now()))
+            {
+                return Err(CompilerError::WriteError(Arc::new(e)));
+            }
+            return Ok(());
+        }
+
+        let mut temp_path = OsString::from(path.as_os_str());
+        temp_path.push(format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+        let temp_path = PathBuf::from(temp_path);
+
+        let file = File::create(&temp_path);
+        if let Err(e) = file {
+            return Err(CompilerError::WriteError(Arc::new(e)));
+        }
+
+        let mut file = file.unwrap();
+        if let Err(e) = file.write_all(bytes) {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(CompilerError::WriteError(Arc::new(e)));
+        }
+        drop(file);
+
+        if let Err(e) = std::fs::rename(&temp_path, path) {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(CompilerError::WriteError(Arc::new(e)));
+        }
+        Ok(())
+    }
+
+    /// Like `compile_from_file`, but infers the shader kind from the file extension. The entry
+    /// point set for that stage with `CompilerBuilder::with_stage_entry_points` is used, if any.
+    /// See `CompilerBuilder::with_infer_fallback` for files without a stage extension.
+    #[cfg(feature = "fs")]
+    pub fn compile_from_file_auto<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+        cache: bool,
+    ) -> Result<Vec<u32>, CompilerError> {
+        let kind = match shader_kind_from_path(path.as_ref()) {
+            Some(kind) => kind,
+            None if self.infer_fallback => {
+                self.warning_sink.report(&format!(
+                    "File {} has no shader stage extension, falling back to ShaderKind::InferFromSource",
+                    path.as_ref().display()
+                ));
+                ShaderKind::InferFromSource
+            }
+            None => {
+                return Err(CompilerError::UnknownShaderKind(
+                    path.as_ref().display().to_string(),
+                ))
+            }
+        };
+
+        let stage_entry_point = self
+            .stage_entry_points
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, name)| name.clone());
+        match stage_entry_point {
+            Some(name) => {
+                let entry_point = std::mem::replace(&mut self.entry_point, name);
+                let result = self.compile_from_file(path, kind, cache);
+                self.entry_point = entry_point;
+                result
+            }
+            None => self.compile_from_file(path, kind, cache),
+        }
+    }
+}
+
+// Most tests compile the files in test-spirv
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use crate::*;
+
+    /// A directory in the system temp dir that is removed again when dropped, even when the
+    /// test fails.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("spirv-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_include() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_include_dirs() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dirs(vec!["does-not-exist", "test-spirv"])
+            .build()
+            .unwrap();
+        assert_eq!(
+            compiler.include_dirs(),
+            vec![
+                PathBuf::from("does-not-exist"),
+                Path::new("test-spirv").canonicalize().unwrap()
+            ]
+        );
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_add_include_dir() {
+        let mut compiler = Compiler::new().unwrap();
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+        assert!(result.is_err());
+
+        compiler.add_include_dir("test-spirv");
+        compiler.add_include_dir("./test-spirv");
+        assert_eq!(compiler.include_dirs().len(), 1);
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_try_include_dir() {
+        assert!(CompilerBuilder::new()
+            .try_with_include_dir("test-spirv")
+            .is_ok());
+        assert!(CompilerBuilder::new()
+            .try_with_include_dir("does-not-exist")
+            .is_err());
+    }
+
+    #[test]
+    fn test_include_rel() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let result = compiler.compile_from_file(
+            "test-spirv/test-include-rel.vert",
+            ShaderKind::Vertex,
+            false,
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_with_macro() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .with_macro("MY_MACRO", Some("1"))
+            .build()
+            .unwrap();
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_compile_for_env() {
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+        let source = "#version 450\nlayout(location = 0) out vec4 color;\nvoid main() {}";
+
+        let vulkan = compiler
+            .compile_from_string(source, ShaderKind::Fragment)
+            .unwrap();
+        let opengl = compiler
+            .compile_from_string_for_env(
+                source,
+                ShaderKind::Fragment,
+                TargetEnv::OpenGL,
+                shaderc::EnvVersion::OpenGL4_5 as u32,
+            )
+            .unwrap();
+        assert_ne!(vulkan, opengl);
+        assert_eq!(
+            compiler
+                .compile_from_string(source, ShaderKind::Fragment)
+                .unwrap(),
+            vulkan
+        );
+    }
+
+    #[test]
+    fn test_macro_overlay() {
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+        let source = std::fs::read_to_string("test-spirv/test-macro.vert").unwrap();
+
+        let result = compiler.compile_from_string_with_macros(
+            &source,
+            ShaderKind::Vertex,
+            &[("MY_MACRO", Some("1"))],
+        );
+        assert!(result.is_ok());
+        // The overlay does not stick to the compiler
+        let result = compiler.compile_from_string(&source, ShaderKind::Vertex);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_without_macro() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_compile_auto() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let result = compiler.compile_from_file_auto("test-spirv/test-include.vert", false);
+        assert!(result.is_ok());
+
+        let result = compiler.compile_from_file_auto("test-spirv/structs.glsl", false);
+        assert!(matches!(result, Err(CompilerError::UnknownShaderKind(_))));
+    }
+
+    #[test]
+    fn test_include_not_found() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let result = compiler.compile_from_string(
+            "#version 450\n#include \"missing.glsl\"\nvoid main() {}",
+            ShaderKind::Vertex,
+        );
+        match result {
+            Err(CompilerError::IncludeNotFound {
+                requested,
+                requested_by,
+                searched,
+            }) => {
+                assert_eq!(requested, "missing.glsl");
+                assert_eq!(requested_by, "memory");
+                assert_eq!(
+                    searched,
+                    vec![
+                        PathBuf::from(""),
+                        Path::new("test-spirv").canonicalize().unwrap()
+                    ]
+                );
+            }
+            _ => panic!("expected a missing include"),
+        }
+
+        // Other errors are still reported as compilation errors
+        let result =
+            compiler.compile_from_string("#version 450\nvoid main() { x; }", ShaderKind::Vertex);
+        assert!(matches!(result, Err(CompilerError::Log(_))));
+    }
+
+    #[test]
+    fn test_include_resolver() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_resolver(|requested, _, _, _| match requested {
+                "asset:42" => Ok(ResolvedInclude {
+                    resolved_name: String::from("asset:42"),
+                    content: String::from("#define VALUE 42"),
+                }),
+                _ => Err(format!("Unknown asset: {}", requested)),
+            })
+            .build()
+            .unwrap();
+
+        let result = compiler.compile_from_string(
+            "#version 450\n#include \"asset:42\"\nlayout(location = 0) out int v;\nvoid main() { v = VALUE; }",
+            ShaderKind::Vertex,
+        );
+        assert!(result.is_ok());
+        assert!(compiler.last_includes().is_empty());
+
+        let result = compiler.compile_from_string(
+            "#version 450\n#include \"asset:7\"\nvoid main() {}",
+            ShaderKind::Vertex,
+        );
+        match result {
+            Err(CompilerError::Log(e)) => assert!(e.description.contains("Unknown asset: asset:7")),
+            _ => panic!("expected the error of the resolver"),
+        }
+    }
+
+    #[test]
+    fn test_virtual_include() {
+        let mut compiler = CompilerBuilder::new()
+            .with_virtual_include(
+                "structs.glsl",
+                String::from("struct TestStruct { float a; };"),
+            )
+            .build()
+            .unwrap();
+
+        let result = compiler.compile_from_string(
+            "#version 450\n\
+             #include <structs.glsl>\n\
+             layout(std430, set = 0, binding = 1) buffer readonly testStructBuffer { TestStruct structs[]; };\n\
+             void main() {}",
+            ShaderKind::Vertex,
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_include_nul() {
+        let dir = std::env::temp_dir().join(format!("spirv-include-nul-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("file.glsl"), "#define VALUE 1\0").unwrap();
+
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir(&dir)
+            .with_virtual_include("virtual.glsl", String::from("#define VALUE 1\0"))
+            .build()
+            .unwrap();
+        for name in ["file.glsl", "virtual.glsl"].iter() {
+            let result = compiler.compile_from_string(
+                &format!("#version 450\n#include <{}>\nvoid main() {{}}", name),
+                ShaderKind::Vertex,
+            );
+            match result {
+                Err(CompilerError::Log(e)) => assert!(e.description.contains("NUL character")),
+                _ => panic!("expected {} to be rejected", name),
+            }
+        }
+
+        let mut compiler = CompilerBuilder::new()
+            .with_include_resolver(|requested, _, _, _| {
+                Ok(ResolvedInclude {
+                    resolved_name: String::from(requested),
+                    content: String::from("\0"),
+                })
+            })
+            .build()
+            .unwrap();
+        let result = compiler.compile_from_string(
+            "#version 450\n#include \"asset:42\"\nvoid main() {}",
+            ShaderKind::Vertex,
+        );
+        assert!(matches!(result, Err(CompilerError::Log(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_max_include_depth() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .with_max_include_depth(1)
+            .build()
+            .unwrap();
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+
+        let result =
+            compiler.compile_from_string("#version 450\nvoid main() { x; }", ShaderKind::Fragment);
+        match result {
+            Err(CompilerError::Log(e)) => {
+                assert_eq!(e.kind, Some(ShaderKind::Fragment));
+                assert_eq!(e.entry_point.as_deref(), Some("main"));
+                assert!(e.to_string().contains("kind: Fragment, entry point: main"));
+            }
+            _ => panic!("expected a compilation error"),
+        }
+
+        match compiler.preprocess_from_string("#error failed") {
+            Err(CompilerError::Log(e)) => assert_eq!((e.kind, e.entry_point), (None, None)),
+            _ => panic!("expected a preprocessing error"),
+        }
+    }
+
+    #[test]
+    fn test_include_error_location() {
+        let dir = std::env::temp_dir().join(format!("spirv-include-line-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("bad.glsl"),
+            "// Line 1\nvec4 broken() { return x; }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.vert"),
+            "#version 450\n#extension GL_GOOGLE_include_directive : require\n\n\
+             #include \"bad.glsl\"\nvoid main() {}\n",
+        )
+        .unwrap();
+
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+        let result = compiler.compile_from_file(dir.join("main.vert"), ShaderKind::Vertex, false);
+        match result {
+            Err(CompilerError::Log(e)) => {
+                let diagnostic = &e.diagnostics()[0];
+                assert!(diagnostic.file.as_ref().unwrap().ends_with("bad.glsl"));
+                assert_eq!(diagnostic.line, 2);
+            }
+            _ => panic!("expected a compilation error"),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_virtual_base_dir() {
+        let mut compiler = CompilerBuilder::new()
+            .with_virtual_base_dir(PathBuf::from("test-spirv"))
+            .build()
+            .unwrap();
+
+        let source = "#version 450\n#include \"structs.glsl\"\nvoid main() {}";
+        assert!(compiler
+            .compile_from_string(source, ShaderKind::Vertex)
+            .is_ok());
+        assert!(compiler
+            .compile_from_named_string(source, ShaderKind::Vertex, "generated/a.vert")
+            .is_err());
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-cycle.vert", ShaderKind::Vertex, false);
+        match result {
+            Err(CompilerError::Log(e)) => {
+                assert!(e.description.contains("Include cycle detected"));
+                assert!(e.description.contains("cycle-b.glsl -> "));
+                assert_eq!(
+                    e.file,
+                    Some(
+                        Path::new("test-spirv/test-cycle.vert")
+                            .canonicalize()
+                            .unwrap()
+                    )
+                );
+            }
+            _ => panic!("expected an include cycle error"),
+        }
+
+        // Include guards end the cycle
+        let result =
+            compiler.compile_from_file("test-spirv/test-guard.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_last_includes() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+        assert_eq!(
+            compiler.last_includes(),
+            vec![Path::new("test-spirv")
+                .canonicalize()
+                .unwrap()
+                .join("structs.glsl")]
+        );
+    }
+
+    #[test]
+    fn test_write_depfile() {
+        let compiler = Compiler::new().unwrap();
+        let output = std::env::temp_dir().join(format!("spirv-depfile-{}.d", std::process::id()));
+        compiler
+            .write_depfile(
+                &output,
+                "out/test include.spv",
+                &[
+                    PathBuf::from("test-spirv/test-include.vert"),
+                    PathBuf::from("test-spirv/$#.glsl"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "out/test\\ include.spv: \\\n  test-spirv/test-include.vert \\\n  test-spirv/$$\\#.glsl\n"
+        );
+        // Cleanup
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = vec![(Limit::MaxDrawBuffers, 4), (Limit::MaxClipDistances, 2)];
+        let builder = CompilerBuilder::new().with_limits(limits);
+        assert_eq!(
+            builder.settings,
+            vec![
+                CompileSetting::Limit(Limit::MaxDrawBuffers, 4),
+                CompileSetting::Limit(Limit::MaxClipDistances, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_opt_level() {
+        let builder = CompilerBuilder::new();
+        assert_eq!(builder.opt_level(), OptimizationLevel::Zero);
+
+        let builder = builder
+            .with_opt_level(OptimizationLevel::Size)
+            .with_opt_level(OptimizationLevel::Performance);
+        assert_eq!(builder.opt_level(), OptimizationLevel::Performance);
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let source = "#version 450\nlayout(location = 0) out vec4 named_output;\nvoid main() { named_output = vec4(1.0); }";
+        let base = CompilerBuilder::new().with_debug_info(true);
+
+        let mut debug = base.clone().build().unwrap();
+        let mut release = base.with_debug_info(false).build().unwrap();
+        let debug = debug
+            .compile_from_string(source, ShaderKind::Fragment)
+            .unwrap();
+        let release = release
+            .compile_from_string(source, ShaderKind::Fragment)
+            .unwrap();
+        assert!(release.len() < debug.len());
+    }
+
+    #[test]
+    fn test_debug_info_mode() {
+        let compile = |mode| {
+            CompilerBuilder::new()
+                .with_debug_info_mode(mode)
+                .with_macro("MY_MACRO", Some("1"))
+                .build()
+                .unwrap()
+                .compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false)
+                .unwrap()
+        };
+
+        let full = compile(DebugInfoMode::Full);
+        let lines = compile(DebugInfoMode::LinesOnly);
+        let none = compile(DebugInfoMode::None);
+        assert!(none.len() < lines.len() && lines.len() < full.len());
+        assert!(reflect(&lines).is_ok());
+    }
+
+    #[test]
+    fn test_builder_clone() {
+        let base = CompilerBuilder::new().with_include_dir("test-spirv");
+        let mut with_macro = base
+            .clone()
+            .with_macro("MY_MACRO", Some("1"))
+            .build()
+            .unwrap();
+        let mut without_macro = base.build().unwrap();
+
+        let result =
+            with_macro.compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+        let result = without_macro.compile_from_file(
+            "test-spirv/test-macro.vert",
+            ShaderKind::Vertex,
+            false,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_include_dir_dedup() {
+        let builder = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .with_include_dirs(vec!["./test-spirv", "test-spirv/", "does-not-exist"])
+            .with_include_dir("does-not-exist");
+
+        assert_eq!(
+            builder.include_dirs,
+            vec![
+                Path::new("test-spirv").canonicalize().unwrap(),
+                PathBuf::from("does-not-exist")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_free_functions() {
+        assert!(compile_glsl("#version 450\nvoid main() {}", ShaderKind::Compute).is_ok());
+        assert!(compile_file("test-spirv/test-reflect.frag", ShaderKind::Fragment).is_ok());
+    }
+
+    #[test]
+    fn test_emit_c_header() {
+        let header = emit_c_header(&[0x0723_0203, 0x0001_0000], "foo_vert_spv");
+        assert_eq!(
+            header,
+            "#pragma once\n#include <stdint.h>\n\n\
+             static const uint32_t foo_vert_spv[] = {\n    0x07230203, 0x00010000,\n};\n\
+             static const uint32_t foo_vert_spv_len = 2;\n"
+        );
+    }
+
+    #[test]
+    fn test_webgpu() {
+        // Only older versions of shaderc support WebGPU
+        let mut compiler = match CompilerBuilder::new().with_webgpu().build() {
+            Ok(compiler) => compiler,
+            Err(CompilerError::InitError(_)) => return,
+            Err(e) => panic!("unexpected error: {}", e),
+        };
+        let result =
+            compiler.compile_from_file("test-spirv/test-reflect.frag", ShaderKind::Fragment, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_webgpu_override_order() {
+        // A later target environment replaces WebGPU, so nothing needs to be probed
+        let config = CompilerBuilder::new()
+            .with_webgpu()
+            .with_vulkan_1_2()
+            .into_config();
+        assert_eq!(
+            config.target_env(),
+            Some((TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_2 as u32))
+        );
+        assert!(config.build().is_ok());
+
+        let config = CompilerBuilder::new()
+            .with_vulkan_1_2()
+            .with_webgpu()
+            .into_config();
+        assert_eq!(config.target_env(), Some((TargetEnv::Vulkan, WEBGPU)));
+        assert!(matches!(
+            config.build(),
+            Ok(_) | Err(CompilerError::InitError(_))
+        ));
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(
+            sidecar_path(Path::new("test-spirv/test-macro.vert"), ".spv"),
+            PathBuf::from("test-spirv/test-macro.vert.spv")
+        );
+        assert_eq!(
+            sidecar_path(Path::new("test-macro.vert"), ".spv"),
+            PathBuf::from("test-macro.vert.spv")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_sidecar_path_windows() {
+        assert_eq!(
+            sidecar_path(Path::new(r"\\?\C:\shaders\test.vert"), ".spv"),
+            PathBuf::from(r"\\?\C:\shaders\test.vert.spv")
+        );
+        assert_eq!(
+            sidecar_path(Path::new(r"\\server\share\test.vert"), ".spv"),
+            PathBuf::from(r"\\server\share\test.vert.spv")
+        );
+    }
+
+    #[test]
+    fn test_extension_to_kind() {
+        assert_eq!(extension_to_kind("FRAG"), Some(ShaderKind::Fragment));
+        assert_eq!(extension_to_kind("glsl"), None);
+        for (ext, kind) in SHADER_EXTENSIONS {
+            assert_eq!(default_extension(*kind), *ext);
+        }
+        assert_eq!(default_extension(ShaderKind::DefaultMesh), "mesh");
+        assert_eq!(default_extension(ShaderKind::InferFromSource), "glsl");
+    }
+
+    #[test]
+    fn test_compile_many() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let results = compiler.compile_many(
+            &[
+                ("test-spirv/test-include.vert", ShaderKind::Vertex),
+                ("test-spirv/test-macro.vert", ShaderKind::Vertex),
+            ],
+            false,
+        );
+        assert!(!results.is_all_ok());
+        assert_eq!(results.succeeded.len(), 1);
+        assert_eq!(
+            results.succeeded[0].0,
+            PathBuf::from("test-spirv/test-include.vert")
+        );
+        assert_eq!(
+            results.first_error().map(|(path, _)| path.as_path()),
+            Some(Path::new("test-spirv/test-macro.vert"))
+        );
+        assert_eq!(results.into_results().len(), 2);
+    }
+
+    #[test]
+    fn test_batch_result_order() {
+        let results: BatchResult = vec![
+            (PathBuf::from("a.vert"), Ok(vec![1])),
+            (
+                PathBuf::from("b.vert"),
+                Err(CompilerError::UnknownShaderKind(String::from("b.vert"))),
+            ),
+            (PathBuf::from("c.vert"), Ok(vec![3])),
+        ]
+        .into_iter()
+        .collect();
+
+        let results = results.into_results();
+        let paths: Vec<_> = results.iter().map(|(path, _)| path.as_path()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("a.vert"),
+                Path::new("b.vert"),
+                Path::new("c.vert")
+            ]
+        );
+        assert!(results[0].1.is_ok());
+        assert!(results[1].1.is_err());
+        assert!(results[2].1.is_ok());
+    }
+
+    #[test]
+    fn test_into_config() {
+        let config = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .into_config();
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let config = config.clone();
+                std::thread::spawn(move || {
+                    config.build().unwrap().compile_from_file(
+                        "test-spirv/test-include.vert",
+                        ShaderKind::Vertex,
+                        false,
+                    )
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_config_include_dirs_independent() {
+        let config = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .into_config();
+        let first = config.build().unwrap();
+        let second = config.build().unwrap();
+
+        first.add_include_dir("shaders");
+        assert_eq!(first.include_dirs().len(), 2);
+        assert_eq!(second.include_dirs().len(), 1);
+        assert_eq!(config.build().unwrap().include_dirs().len(), 1);
+    }
+
+    #[test]
+    fn test_compile_parallel() {
+        let compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let results = compiler.compile_parallel(&[
+            (
+                PathBuf::from("test-spirv/test-include.vert"),
+                ShaderKind::Vertex,
+            ),
+            (
+                PathBuf::from("test-spirv/test-macro.vert"),
+                ShaderKind::Vertex,
+            ),
+            (
+                PathBuf::from("test-spirv/test-include-rel.vert"),
+                ShaderKind::Vertex,
+            ),
+        ]);
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+    }
+
+    #[test]
+    fn test_target_spirv_version() {
+        let mut compiler = CompilerBuilder::new()
+            .with_target_spirv(SpirvVersion::V1_3)
+            .with_macro("MY_MACRO", Some("1"))
+            .build()
+            .unwrap();
+        let binary = compiler
+            .compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false)
+            .unwrap();
+        assert_eq!(spirv_version_of(&binary), Some((1, 3)));
+    }
+
+    #[test]
+    fn test_strip_debug() {
+        let mut compiler = CompilerBuilder::new()
+            .with_debug_info(true)
+            .with_macro("MY_MACRO", Some("1"))
+            .build()
+            .unwrap();
+        let binary = compiler
+            .compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false)
+            .unwrap();
+
+        let stripped = strip_debug(&binary).unwrap();
+        assert!(stripped.len() < binary.len());
+        assert!(reflect(&stripped).is_ok());
+    }
+
+    #[test]
+    fn test_optimize_spirv() {
+        let mut compiler = CompilerBuilder::new()
+            .with_debug_info(true)
+            .with_macro("MY_MACRO", Some("1"))
+            .build()
+            .unwrap();
+        let binary = compiler
+            .compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false)
+            .unwrap();
+
+        let result = compiler.optimize_spirv(&binary, OptimizationLevel::Zero);
+        assert_eq!(result.unwrap(), binary);
+        let result = compiler.optimize_spirv(&binary, OptimizationLevel::Size);
+        assert_eq!(result.unwrap(), strip_debug(&binary).unwrap());
+        assert!(matches!(
+            compiler.optimize_spirv(&binary[..4], OptimizationLevel::Performance),
+            Err(CompilerError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_get_cached() {
+        let mut compiler = CompilerBuilder::new()
+            .with_macro("MY_MACRO", Some("1"))
+            .build()
+            .unwrap();
+        assert!(compiler.get_cached("test-spirv/test-macro.vert").is_none());
+
+        let binary = compiler
+            .compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false)
+            .unwrap();
+        let compiler = &compiler;
+        assert_eq!(
+            compiler.get_cached("test-spirv/test-macro.vert"),
+            Some(binary)
+        );
+    }
+
+    #[test]
+    fn test_compile_shared() {
+        let cache_dir = std::env::temp_dir().join(format!("spirv-shared-{}", std::process::id()));
+        let mut compiler = CompilerBuilder::new()
+            .with_macro("MY_MACRO", Some("1"))
+            .with_cache_dir(cache_dir.clone())
+            .build()
+            .unwrap();
+
+        let first = compiler
+            .compile_from_file_shared("test-spirv/test-macro.vert", ShaderKind::Vertex, true)
+            .unwrap();
+        let second = compiler
+            .compile_from_file_shared("test-spirv/test-macro.vert", ShaderKind::Vertex, true)
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(compiler.last_compile_source(), CompileSource::InMemoryCache);
+        // Cleanup
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_infer_from_source() {
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+
+        let result = compiler.compile_infer_from_source(
+            "#version 450\n#pragma shader_stage(compute)\nvoid main() {}",
+            "compute",
+        );
+        assert!(result.is_ok());
+        let result = compiler.compile_infer_from_source("#version 450\nvoid main() {}", "none");
+        match result {
+            Err(CompilerError::Log(e)) => assert!(e.description.contains("#pragma")),
+            _ => panic!("expected a missing pragma error"),
+        }
+    }
+
+    #[test]
+    fn test_compile_from_reader() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let file = File::open("test-spirv/test-include.vert").unwrap();
+        let result = compiler.compile_from_reader(file, ShaderKind::Vertex, "test-include.vert");
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_compiler_pool() {
+        let pool = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build_pool();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                std::thread::spawn(move || {
+                    pool.compile_from_file(
+                        "test-spirv/test-include.vert",
+                        ShaderKind::Vertex,
+                        false,
+                    )
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_compiler_pool_reentrant() {
+        let pool = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build_pool();
+        let id = pool.id;
+        let pool_compilers = || POOL_COMPILERS.with(|compilers| compilers.borrow().len());
+        let before = pool_compilers();
+
+        let result = pool.with_compiler(|_| {
+            pool.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false)
+        });
+        assert!(result.is_ok());
+        assert!(POOL_COMPILERS.with(|compilers| compilers.borrow().contains_key(&id)));
+
+        let clone = pool.clone();
+        drop(pool);
+        assert_eq!(pool_compilers(), before + 1);
+        drop(clone);
+        assert_eq!(pool_compilers(), before);
+    }
+
+    #[test]
+    fn test_compile_bytes() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let bytes = compiler
+            .compile_from_file_bytes("test-spirv/test-include.vert", ShaderKind::Vertex, false)
+            .unwrap();
+        // SPIR-V magic number in little-endian
+        assert_eq!(&bytes[0..4], &[0x03, 0x02, 0x23, 0x07]);
+    }
+
+    #[test]
+    fn test_named_string() {
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+
+        let result = compiler.compile_from_named_string(
+            "#version 450\nvoid main() { V = vec4(0.0); }",
+            ShaderKind::Vertex,
+            "lighting_permutation_3",
+        );
+        match result {
+            Err(CompilerError::Log(e)) => {
+                assert!(e.description.contains("lighting_permutation_3:2"))
+            }
+            _ => panic!("expected a compilation error"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let path = std::env::temp_dir().join(format!("spirv-utf8-{}.vert", std::process::id()));
+        std::fs::write(&path, b"#version 450\nvoid main() {}\xff\n").unwrap();
+
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+        let result = compiler.compile_from_file(&path, ShaderKind::Vertex, false);
+        match result {
+            Err(CompilerError::LoadError(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                assert!(e.to_string().contains("not valid UTF-8"));
+            }
+            _ => panic!("expected a load error"),
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let result = compiler.compile_from_bytes(b"void main() {}\xff", ShaderKind::Vertex, "net");
+        assert!(
+            matches!(result, Err(CompilerError::LoadError(e)) if e.to_string().starts_with("net"))
+        );
+        let result =
+            compiler.compile_from_bytes(b"#version 450\nvoid main() {}", ShaderKind::Vertex, "net");
+        assert!(result.is_ok());
+
+        let result = compiler.compile_from_bytes(
+            b"#version 450\nvoid main() {}\0",
+            ShaderKind::Vertex,
+            "net",
+        );
+        match result {
+            Err(CompilerError::LoadError(e)) => {
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                assert_eq!(e.to_string(), "net: contains a NUL character at byte 27");
+            }
+            _ => panic!("expected a load error"),
+        }
+        let result = compiler.compile_from_reader(
+            &b"#version 450\n\0void main() {}"[..],
+            ShaderKind::Vertex,
+            "net",
+        );
+        assert!(matches!(result, Err(CompilerError::LoadError(_))));
+    }
+
+    #[test]
+    fn test_nul_source_file() {
+        let path = std::env::temp_dir().join(format!("spirv-nul-{}.vert", std::process::id()));
+        std::fs::write(&path, "#version 450\nvoid main() {}\0").unwrap();
+
+        let mut compiler = CompilerBuilder::new()
+            .with_cache_strategy(CacheStrategy::ContentHash)
+            .build()
+            .unwrap();
+        for cache in [false, true].iter() {
+            match compiler.compile_from_file(&path, ShaderKind::Vertex, *cache) {
+                Err(CompilerError::LoadError(e)) => {
+                    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                    assert_eq!(
+                        e.to_string(),
+                        format!("{}: contains a NUL character at byte 27", path.display())
+                    );
+                }
+                _ => panic!("expected a load error"),
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_error_source() {
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+
+        let result = compiler.compile_from_file("does-not-exist.vert", ShaderKind::Vertex, false);
+        let error = result.unwrap_err();
+        let source = error
+            .source()
+            .and_then(|e| e.downcast_ref::<io::Error>())
+            .unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_compile_dir() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .with_macro("MY_MACRO", Some("1"))
+            .build()
+            .unwrap();
+
+        let results = compiler.compile_dir("test-spirv", false, false);
+        assert!(results
+            .iter()
+            .all(|(path, _)| shader_kind_from_path(path).is_some()));
+        assert!(results
+            .iter()
+            .any(|(path, result)| path.ends_with("test-include.vert") && result.is_ok()));
+    }
+
+    #[test]
+    fn test_compile_to_file() {
+        let dir = std::env::temp_dir().join(format!("spirv-output-{}", std::process::id()));
+        let output = dir.join("out").join("test-include.vert.spv");
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let result =
+            compiler.compile_to_file("test-spirv/test-include.vert", ShaderKind::Vertex, &output);
+        assert!(result.is_ok());
+        let binary = compiler
+            .compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false)
+            .unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), words_to_bytes(&binary));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compile_to_assembly() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let result = compiler
+            .compile_to_assembly_from_file("test-spirv/test-include.vert", ShaderKind::Vertex);
+        assert!(result.unwrap().contains("OpEntryPoint Vertex"));
+
+        let source = "#version 450\nvoid main() {}\0";
+        let result = compiler.compile_to_assembly_from_string(source, ShaderKind::Vertex);
+        assert!(matches!(result, Err(CompilerError::LoadError(_))));
+        let path = std::env::temp_dir().join(format!("spirv-asm-nul-{}.vert", std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let result = compiler.compile_to_assembly_from_file(&path, ShaderKind::Vertex);
+        assert!(matches!(result, Err(CompilerError::LoadError(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_assemble() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let asm = compiler
+            .compile_to_assembly_from_file("test-spirv/test-include.vert", ShaderKind::Vertex)
+            .unwrap();
+        let binary = compiler.assemble_from_string(&asm).unwrap();
+        assert_eq!(spirv_version_of(&binary), Some((1, 0)));
+        assert!(compiler.assemble_from_string("OpNotAnInstruction").is_err());
+        assert!(matches!(
+            compiler.assemble_from_string("OpCapability Shader\0"),
+            Err(CompilerError::LoadError(_))
+        ));
+    }
+
+    #[test]
+    fn test_compile_entry_points() {
+        let mut compiler = CompilerBuilder::new()
+            .with_source_language(SourceLanguage::HLSL)
+            .build()
+            .unwrap();
+        let source = "float4 Red() : SV_TARGET { return float4(1, 0, 0, 1); }\n\
+                      float4 Green() : SV_TARGET { return float4(0, 1, 0, 1); }";
+
+        let binaries = compiler
+            .compile_entry_points(source, ShaderKind::Fragment, &["Red", "Green"])
+            .unwrap();
+        assert_eq!(binaries.len(), 2);
+        assert_ne!(binaries["Red"], binaries["Green"]);
+        assert!(compiler
+            .compile_entry_points(source, ShaderKind::Fragment, &["Blue"])
+            .is_err());
+        assert_eq!(compiler.entry_point, "main");
+    }
+
+    #[test]
+    fn test_preprocess() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+
+        let result = compiler.preprocess_from_file("test-spirv/test-include.vert");
+        assert!(result.unwrap().contains("struct TestStruct"));
+
+        let source = "#version 450\n#define VALUE 1\0";
+        let result = compiler.preprocess_from_string(source);
+        assert!(matches!(result, Err(CompilerError::LoadError(_))));
+        let path = std::env::temp_dir().join(format!("spirv-pre-nul-{}.vert", std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let result = compiler.preprocess_from_file(&path);
+        assert!(matches!(result, Err(CompilerError::LoadError(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_entry_point() {
+        let mut compiler = CompilerBuilder::new()
+            .with_source_language(SourceLanguage::HLSL)
+            .with_entry_point("VSMain")
+            .build()
+            .unwrap();
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-entry.hlsl", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_set_entry_point_cached() {
+        let dir = std::env::temp_dir().join(format!("spirv-entry-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test-entries.hlsl");
+        std::fs::write(
+            &path,
+            "float4 VSMain(float4 position : POSITION) : SV_POSITION { return position; }\n\
+             float4 VSDouble(float4 position : POSITION) : SV_POSITION { return position * 2; }\n",
+        )
+        .unwrap();
+
+        let mut compiler = CompilerBuilder::new()
+            .with_source_language(SourceLanguage::HLSL)
+            .with_entry_point("VSMain")
+            .build()
+            .unwrap();
+        let first = compiler
+            .compile_from_file(&path, ShaderKind::Vertex, true)
+            .unwrap();
+        compiler.set_entry_point("VSDouble");
+        let second = compiler
+            .compile_from_file(&path, ShaderKind::Vertex, true)
+            .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(compiler.last_compile_source(), CompileSource::Compiled);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hlsl_vulkan_preset() {
+        let mut compiler = CompilerBuilder::new()
+            .with_hlsl_vulkan_preset()
+            .with_entry_point("VSMain")
+            .build()
+            .unwrap();
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-entry.hlsl", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_source_language_auto() {
+        let mut compiler = CompilerBuilder::new()
+            .with_source_language_auto()
+            .with_macro("MY_MACRO", Some("1"))
+            .with_entry_point("VSMain")
+            .build()
+            .unwrap();
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-entry.hlsl", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+        compiler.set_entry_point("main");
+        let result =
+            compiler.compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_stage_entry_points() {
+        let dir = std::env::temp_dir().join(format!("spirv-stages-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy("test-spirv/test-entry.hlsl", dir.join("test-entry.vert")).unwrap();
+        std::fs::write(
+            dir.join("test-entry.frag"),
+            "float4 PSMain() : SV_TARGET { return float4(1, 0, 0, 1); }",
+        )
+        .unwrap();
+
+        let mut compiler = CompilerBuilder::new()
+            .with_source_language(SourceLanguage::HLSL)
+            .with_stage_entry_points(vec![
+                (ShaderKind::Vertex, "VSMain"),
+                (ShaderKind::Fragment, "PSMain"),
+            ])
+            .build()
+            .unwrap();
+        assert!(compiler
+            .compile_from_file_auto(dir.join("test-entry.vert"), false)
+            .is_ok());
+        assert!(compiler
+            .compile_from_file_auto(dir.join("test-entry.frag"), false)
+            .is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_auto_map_locations() {
+        let source = "#version 450\nin vec4 vertex;\nvoid main() { gl_Position = vertex; }";
+
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+        let result = compiler.compile_from_string(source, ShaderKind::Vertex);
+        assert!(result.is_err());
+
+        let mut compiler = CompilerBuilder::new()
+            .with_auto_map_locations(true)
+            .with_auto_combined_image_sampler(true)
+            .build()
+            .unwrap();
+        let result = compiler.compile_from_string(source, ShaderKind::Vertex);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_warning_sink() {
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let sink = warnings.clone();
+        let mut compiler = CompilerBuilder::new()
+            .with_warning_sink(WarningSink::callback(move |message| {
+                sink.lock().unwrap().push(String::from(message))
+            }))
+            .build()
+            .unwrap();
+
+        let result = compiler.compile_from_string(
+            "#version 450\n#extension GL_UNKNOWN_extension : warn\nvoid main() {}",
+            ShaderKind::Compute,
+        );
+        assert!(result.is_ok());
+        assert_eq!(warnings.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_infer_fallback() {
+        let dir = std::env::temp_dir().join(format!("spirv-infer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("blur.glsl");
+        std::fs::write(
+            &path,
+            "#version 450\n#pragma shader_stage(compute)\nvoid main() {}\n",
+        )
+        .unwrap();
+
+        let result = Compiler::new()
+            .unwrap()
+            .compile_from_file_auto(&path, false);
+        assert!(matches!(result, Err(CompilerError::UnknownShaderKind(_))));
+
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let sink = warnings.clone();
+        let mut compiler = CompilerBuilder::new()
+            .with_infer_fallback(true)
+            .with_warning_sink(WarningSink::callback(move |message| {
+                sink.lock().unwrap().push(String::from(message))
+            }))
+            .build()
+            .unwrap();
+        assert!(compiler.compile_from_file_auto(&path, false).is_ok());
+        assert!(warnings.lock().unwrap()[0].contains("InferFromSource"));
+
+        assert_eq!(
+            extension_to_kind_or_infer("glsl"),
+            ShaderKind::InferFromSource
+        );
+        assert_eq!(extension_to_kind_or_infer("comp"), ShaderKind::Compute);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_compile_observer() {
+        let stats = Arc::new(Mutex::new(Vec::new()));
+        let observed = stats.clone();
+        let mut compiler = CompilerBuilder::new()
+            .with_macro("MY_MACRO", Some("1"))
+            .with_compile_observer(move |stats| observed.lock().unwrap().push(*stats))
+            .build()
+            .unwrap();
+
+        let binary = compiler
+            .compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false)
+            .unwrap();
+        compiler
+            .compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, true)
+            .unwrap();
+
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].word_count, binary.len());
+        assert_eq!(stats[0].kind, ShaderKind::Vertex);
+        assert!(!stats[0].cache_hit && stats[1].cache_hit);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .with_validate(true)
+            .build()
+            .unwrap();
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_take_last_warnings() {
+        let mut compiler = CompilerBuilder::new()
+            .with_collect_warnings(true)
+            .build()
+            .unwrap();
+
+        let source = "#version 450\n#extension GL_UNKNOWN_extension : warn\nvoid main() {}\n";
+        let result = compiler.compile_from_string(source, ShaderKind::Vertex);
+        assert!(result.is_ok());
+        assert!(compiler
+            .take_last_warnings()
+            .contains("GL_UNKNOWN_extension"));
+        assert!(compiler.take_last_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_cache_touch_unchanged() {
+        let dir = std::env::temp_dir().join(format!("spirv-touch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("test-include.vert");
+        std::fs::copy("test-spirv/test-include.vert", &source).unwrap();
+        std::fs::copy("test-spirv/structs.glsl", dir.join("structs.glsl")).unwrap();
+
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+        let binary = compiler.compile_from_file(&source, ShaderKind::Vertex, true);
+        assert!(binary.is_ok());
 
-        let requested_path = PathBuf::from(String::from(requested_source));
-        let requesting_path = PathBuf::from(String::from(requesting_source));
-
-        if include_type == IncludeType::Standard {
-            for path in include_dirs {
-                let final_path = path.join(requested_path.as_path());
-                if final_path.exists() {
-                    if let Ok(mut file) = File::open(final_path.clone()) {
-                        let mut source = String::new();
-                        file.read_to_string(&mut source).unwrap();
-                        return Ok(ResolvedInclude {
-                            resolved_name: String::from(final_path.to_str().unwrap()),
-                            content: source,
-                        });
-                    }
-                }
-            }
+        // Make the sidecar look stale, recompiling gives identical bytes
+        let sidecar = dir.join("test-include.vert.spv");
+        let contents = std::fs::read(&sidecar).unwrap();
+        File::options()
+            .write(true)
+            .open(&sidecar)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
 
-            return Err(format!(
-                "Could not find file: {}",
-                requested_source
-            ));
-        } else if include_type == IncludeType::Relative {
-            // #include ""
-            let base_folder = requesting_path.as_path().parent().unwrap();
-            let final_path = base_folder.join(requested_path.clone());
-            if final_path.exists() {
-                if let Ok(mut file) = File::open(final_path.clone()) {
-                    let mut source = String::new();
-                    file.read_to_string(&mut source).unwrap();
-                    return Ok(ResolvedInclude {
-                        resolved_name: String::from(final_path.to_str().unwrap()),
-                        content: source,
-                    });
-                }
-            }
+        compiler.clear_cache();
+        let result = compiler.compile_from_file(&source, ShaderKind::Vertex, true);
+        assert_eq!(result.unwrap(), binary.unwrap());
+        assert_eq!(std::fs::read(&sidecar).unwrap(), contents);
+        assert!(
+            sidecar.metadata().unwrap().modified().unwrap()
+                >= source.metadata().unwrap().modified().unwrap()
+        );
 
-            for path in include_dirs {
-                let final_path = path.join(requested_path.as_path());
-                if final_path.exists() {
-                    if let Ok(mut file) = File::open(final_path.clone()) {
-                        let mut source = String::new();
-                        file.read_to_string(&mut source).unwrap();
-                        return Ok(ResolvedInclude {
-                            resolved_name: String::from(final_path.to_str().unwrap()),
-                            content: source,
-                        });
-                    }
-                }
-            }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-            return Err(format!(
-                "Could not find file: {}",
-                requested_source
-            ));
-        }
+    #[test]
+    fn test_vulkan_presets() {
+        let builder = CompilerBuilder::new().with_vulkan_1_1();
+        assert!(builder.settings.contains(&CompileSetting::TargetEnv(
+            TargetEnv::Vulkan,
+            (1 << 22) | (1 << 12)
+        )));
+        assert!(builder
+            .settings
+            .contains(&CompileSetting::TargetSpirv(SpirvVersion::V1_3)));
 
-        Err(format!(
-            "Unkown error resolving file: {}",
-            requested_source
-        ))
+        let mut compiler = CompilerBuilder::new()
+            .with_vulkan_1_2()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
     }
 
-    pub fn compile_from_string(
-        &mut self,
-        source: &str,
-        kind: shaderc::ShaderKind,
-    ) -> Result<Vec<u32>, CompilerError> {
-        let binary_result =
-            self.compiler
-                .compile_into_spirv(source, kind, "memory", "main", Some(&self.options));
+    #[test]
+    fn test_raytracing() {
+        let mut compiler = CompilerBuilder::new()
+            .with_raytracing_env()
+            .with_include_dir("test-spirv")
+            .with_macro("MY_MACRO", Some("1"))
+            .build()
+            .unwrap();
 
-        match binary_result {
-            Err(e) => Err(CompilationError {
-                file: None,
-                description: e.to_string(),
-            }
-            .into()),
-            Ok(result) => Ok(result.as_binary().to_vec()),
-        }
+        let result = compiler.compile_from_file_auto("test-spirv/test-raytracing.rgen", false);
+        assert!(result.is_ok());
+        let result = compiler.compile_from_file(
+            "test-spirv/test-raytracing.rchit",
+            ShaderKind::ClosestHit,
+            false,
+        );
+        assert!(result.is_ok());
     }
 
-    pub fn compile_from_file<T: AsRef<Path>>(
-        &mut self,
-        path: T,
-        kind: shaderc::ShaderKind,
-        cache: bool,
-    ) -> Result<Vec<u32>, CompilerError> {
-        let mut precompiled = OsString::from(path.as_ref().as_os_str());
-        precompiled.push(".spv");
-        let precompiled = PathBuf::from(precompiled);
+    #[test]
+    fn test_save_cache() {
+        let saved = std::env::temp_dir().join(format!("spirv-cache-{}.bin", std::process::id()));
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+        let binary = compiler
+            .compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false)
+            .unwrap();
+        assert!(compiler.save_cache(&saved).is_ok());
 
-        if cache {
-            if let Some(binary) = self.compile_cache.get(&path.as_ref().to_path_buf()) {
-                return Ok(binary.clone());
-            }
-
-            if precompiled.exists() && !self.has_macros {
-                let should_recompile: bool = if let (Ok(meta_data), Ok(pre_meta_data)) =
-                    (path.as_ref().metadata(), precompiled.metadata())
-                {
-                    let source_last_modified = meta_data.modified();
-                    let last_modified = pre_meta_data.modified();
-                    if let (Ok(source_last_modified), Ok(last_modified)) =
-                        (source_last_modified, last_modified)
-                    {
-                        source_last_modified.cmp(&last_modified) == Ordering::Less
-                    } else {
-                        true
-                    }
-                } else {
-                    true
-                };
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .build()
+            .unwrap();
+        assert!(compiler.load_cache(&saved).is_ok());
+        assert_eq!(compiler.cache_len(), 1);
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, true);
+        assert_eq!(result.unwrap(), binary);
 
-                // Only load pre-compiled files if they are up to date
-                if should_recompile {
-                    if let Ok(mut file) = File::open(&precompiled) {
-                        let mut bytes = Vec::new();
-                        file.read_to_end(&mut bytes).unwrap();
-                        let bytes: Vec<u32> = Vec::from(unsafe {
-                            std::slice::from_raw_parts(
-                                bytes.as_ptr() as *const u32,
-                                bytes.len() / 4,
-                            )
-                        });
-
-                        self.compile_cache
-                            .insert(path.as_ref().to_path_buf(), bytes.clone());
-                        return Ok(bytes);
-                    }
-                }
-            }
-        }
+        // A compiler with other options restores nothing
+        let mut compiler = CompilerBuilder::new()
+            .with_macro("MY_MACRO", Some("1"))
+            .build()
+            .unwrap();
+        assert!(compiler.load_cache(&saved).is_ok());
+        assert_eq!(compiler.cache_len(), 0);
 
-        let file = File::open(&path);
-        if let Err(e) = file {
-            return Err(CompilerError::LoadError(e.to_string()));
-        }
+        std::fs::remove_file(&saved).unwrap();
+    }
 
-        let mut file = file.unwrap();
-        let mut source = String::new();
-        file.read_to_string(&mut source).unwrap();
+    #[test]
+    fn test_reflect() {
+        let mut compiler = CompilerBuilder::new().build().unwrap();
+        let binary = compiler
+            .compile_from_file("test-spirv/test-reflect.frag", ShaderKind::Fragment, false)
+            .unwrap();
 
-        let binary_result = self.compiler.compile_into_spirv(
-            source.as_str(),
-            kind,
-            path.as_ref().to_str().unwrap(),
-            "main",
-            Some(&self.options),
+        let reflection = reflect(&binary).unwrap();
+        let mut bindings: Vec<_> = reflection
+            .bindings
+            .iter()
+            .map(|binding| (binding.set, binding.binding, binding.kind, binding.count))
+            .collect();
+        bindings.sort_by_key(|(set, binding, _, _)| (*set, *binding));
+        assert_eq!(
+            bindings,
+            vec![
+                (0, 0, DescriptorKind::UniformBuffer, 1),
+                (0, 1, DescriptorKind::CombinedImageSampler, 4),
+                (1, 0, DescriptorKind::StorageBuffer, 1),
+            ]
+        );
+        assert_eq!(
+            reflection.push_constants,
+            vec![PushConstantRange {
+                offset: 0,
+                size: 20
+            }]
         );
+    }
 
-        if let Err(e) = binary_result {
-            return Err(CompilationError {
-                file: Some(path.as_ref().to_path_buf()),
-                description: e.to_string(),
-            }
-            .into());
-        }
+    #[test]
+    fn test_default_version_profile() {
+        let mut compiler = CompilerBuilder::new()
+            .with_default_version_profile(450, GlslProfile::Core)
+            .build()
+            .unwrap();
 
-        let binary_result = binary_result.unwrap();
-        if binary_result.get_num_warnings() > 0 {
-            eprintln!(
-                "File {} produced {} warnings: {}",
-                path.as_ref().display(),
-                binary_result.get_num_warnings(),
-                binary_result.get_warning_messages()
-            );
-        }
-        let bytes = binary_result.as_binary().to_vec();
+        let result = compiler.compile_from_string("void main() {}", ShaderKind::Vertex);
+        assert!(result.is_ok());
 
-        if cache {
-            let file = File::create(&precompiled);
-            if let Err(e) = file {
-                return Err(CompilerError::WriteError(e.to_string()));
-            }
+        // Sources with a version keep it, 100 es does not support compute shaders
+        let result =
+            compiler.compile_from_string("#version 100 es\nvoid main() {}", ShaderKind::Compute);
+        assert!(result.is_err());
 
-            let mut file = file.unwrap();
+        // Line numbers refer to the original source
+        match compiler.compile_from_string("\nvoid main() { x; }", ShaderKind::Vertex) {
+            Err(CompilerError::Log(e)) => assert_eq!(e.diagnostics()[0].line, 2),
+            _ => panic!("expected a compilation error"),
+        }
+    }
 
-            if let Err(e) = file.write_all(unsafe {
-                std::slice::from_raw_parts(bytes.as_ptr() as *const u8, bytes.len() * 4)
-            }) {
-                return Err(CompilerError::WriteError(e.to_string()));
+    #[test]
+    fn test_multiple_errors() {
+        let mut compiler = Compiler::new().unwrap();
+        let result = compiler.compile_from_string(
+            "#version 450\nvoid main() {\n    float a = missing_a;\n    float b = missing_b;\n}",
+            ShaderKind::Vertex,
+        );
+        match result {
+            Err(CompilerError::Log(e)) => {
+                let errors: Vec<_> = e
+                    .diagnostics()
+                    .into_iter()
+                    .filter(|diagnostic| diagnostic.severity == Severity::Error)
+                    .collect();
+                assert_eq!(errors.len(), 2);
+                assert_eq!((errors[0].line, errors[1].line), (3, 4));
+                assert!(errors[0].message.contains("missing_a"));
+                assert!(errors[1].message.contains("missing_b"));
             }
+            _ => panic!("expected a compilation error"),
         }
-
-        self.compile_cache
-            .insert(path.as_ref().to_path_buf(), bytes.clone());
-        Ok(bytes)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::*;
 
     #[test]
-    fn test_include() {
+    fn test_preamble() {
         let mut compiler = CompilerBuilder::new()
-            .with_include_dir("test-spirv")
+            .with_preamble("#define MY_MACRO 1")
             .build()
             .unwrap();
 
         let result =
-            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+            compiler.compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false);
         assert!(result.is_ok());
+
+        // Line numbers refer to the original source
+        match compiler.compile_from_string("#version 450\n\nvoid main() { x; }", ShaderKind::Vertex)
+        {
+            Err(CompilerError::Log(e)) => assert_eq!(e.diagnostics()[0].line, 3),
+            _ => panic!("expected a compilation error"),
+        }
     }
 
     #[test]
-    fn test_include_rel() {
+    fn test_cache() {
+        let cached = PathBuf::from("test-spirv/test-macro.vert.spv");
+        if cached.exists() {
+            std::fs::remove_file(&cached).unwrap();
+        }
+
         let mut compiler = CompilerBuilder::new()
             .with_include_dir("test-spirv")
+            .with_macro("MY_MACRO", Some("1"))
             .build()
             .unwrap();
 
+        let result =
+            compiler.compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, true);
+        assert!(result.is_ok());
+        assert!(cached.exists());
+        // Cleanup
+        std::fs::remove_file(cached).unwrap();
+    }
+
+    #[test]
+    fn test_cache_content_hash() {
+        let dir = TempDir::new("content-hash");
+        let builder = CompilerBuilder::new()
+            .with_cache_strategy(CacheStrategy::ContentHash)
+            .with_cache_dir(dir.0.clone());
+        let path = Path::new("test-spirv/test-include-rel.vert");
+
+        let mut compiler = builder.clone().build().unwrap();
+        let first = compiler
+            .compile_from_file(path, ShaderKind::Vertex, true)
+            .unwrap();
+        assert!(compiler.cache_path(path).exists());
+
+        let mut compiler = builder.build().unwrap();
+        let second = compiler
+            .compile_from_file(path, ShaderKind::Vertex, true)
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(compiler.last_compile_source(), CompileSource::DiskCache);
+    }
+
+    #[test]
+    fn test_cache_unused_macro() {
+        let cache_dir = std::env::temp_dir().join(format!("spirv-macro-{}", std::process::id()));
+        let builder = CompilerBuilder::new()
+            .with_cache_strategy(CacheStrategy::ContentHash)
+            .with_cache_dir(cache_dir.clone());
+        let mut compiler = builder.clone().build().unwrap();
         let result = compiler.compile_from_file(
             "test-spirv/test-include-rel.vert",
             ShaderKind::Vertex,
-            false,
+            true,
+        );
+        assert!(result.is_ok());
+
+        // A macro the shader doesn't use leaves the preprocessed source and its key unchanged
+        let mut compiler = builder
+            .with_macro("UNUSED_MACRO", Some("1"))
+            .build()
+            .unwrap();
+        let result = compiler.compile_from_file(
+            "test-spirv/test-include-rel.vert",
+            ShaderKind::Vertex,
+            true,
         );
         assert!(result.is_ok());
+        assert_eq!(compiler.last_compile_source(), CompileSource::DiskCache);
+        // Cleanup
+        std::fs::remove_dir_all(cache_dir).unwrap();
     }
 
     #[test]
-    fn test_with_macro() {
+    fn test_cache_dir() {
+        let cache_dir = std::env::temp_dir().join("spirv-compiler-test-cache-dir");
+        if cache_dir.exists() {
+            std::fs::remove_dir_all(&cache_dir).unwrap();
+        }
+
         let mut compiler = CompilerBuilder::new()
             .with_include_dir("test-spirv")
+            .with_cache_dir(cache_dir.clone())
+            .build()
+            .unwrap();
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, true);
+        assert!(result.is_ok());
+        assert!(!PathBuf::from("test-spirv/test-include.vert.spv").exists());
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 1);
+        // Cleanup
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_corrupt() {
+        type Corruption = fn(&mut Vec<u8>);
+        let cases: &[(&str, Corruption)] = &[
+            // Byte swap the binary in the cache file, keeping its header intact
+            ("swapped", |bytes| {
+                for word in bytes[cache::SIDECAR_HEADER_WORDS * 4..].chunks_exact_mut(4) {
+                    word.reverse();
+                }
+            }),
+            // Flip a bit of the binary, as bit rot would
+            ("flipped", |bytes| {
+                let last = bytes.len() - 1;
+                bytes[last] ^= 1;
+            }),
+            // Drop the last byte, as an interrupted write would
+            ("truncated", |bytes| {
+                bytes.pop();
+            }),
+        ];
+
+        for (name, corrupt) in cases {
+            let dir = TempDir::new(&format!("corrupt-{}", name));
+            let mut compiler = CompilerBuilder::new()
+                .with_macro("MY_MACRO", Some("1"))
+                .with_cache_dir(dir.0.clone())
+                .build()
+                .unwrap();
+            let path = "test-spirv/test-macro.vert";
+            let binary = compiler
+                .compile_from_file(path, ShaderKind::Vertex, true)
+                .unwrap();
+
+            let cached = compiler.cache_path(Path::new(path));
+            let mut bytes = std::fs::read(&cached).unwrap();
+            corrupt(&mut bytes);
+            std::fs::write(&cached, bytes).unwrap();
+
+            compiler.clear_cache();
+            let result = compiler.compile_from_file(path, ShaderKind::Vertex, true);
+            assert_eq!(result.unwrap(), binary, "{}", name);
+            assert_eq!(compiler.last_compile_source(), CompileSource::Compiled);
+        }
+    }
+
+    #[test]
+    fn test_cache_mtime_skips_source() {
+        let dir = TempDir::new("mtime-read");
+        let path = dir.0.join("test-macro.vert");
+        std::fs::copy("test-spirv/test-macro.vert", &path).unwrap();
+        let mut compiler = CompilerBuilder::new()
+            .with_macro("MY_MACRO", Some("1"))
+            .with_cache_dir(dir.0.join("cache"))
+            .build()
+            .unwrap();
+        let binary = compiler
+            .compile_from_file(&path, ShaderKind::Vertex, true)
+            .unwrap();
+
+        // An unreadable source that is older than its cache file is never read
+        std::fs::write(&path, b"\xff").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1))
+            .unwrap();
+        compiler.clear_cache();
+        let result = compiler.compile_from_file(&path, ShaderKind::Vertex, true);
+        assert_eq!(result.unwrap(), binary);
+        assert_eq!(compiler.last_compile_source(), CompileSource::DiskCache);
+    }
+
+    #[test]
+    fn test_recompile_from_file() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("spirv-recompile-{}", std::process::id()));
+        let mut compiler = CompilerBuilder::new()
+            .with_macro("MY_MACRO", Some("1"))
+            .with_cache_dir(cache_dir.clone())
+            .build()
+            .unwrap();
+        let path = "test-spirv/test-macro.vert";
+
+        let binary = compiler
+            .recompile_from_file(path, ShaderKind::Vertex)
+            .unwrap();
+        assert_eq!(compiler.get_cached(path), Some(binary.clone()));
+        assert!(compiler.cache_path(Path::new(path)).exists());
+        assert_eq!(
+            compiler
+                .compile_from_file(path, ShaderKind::Vertex, true)
+                .unwrap(),
+            binary
+        );
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_last_compile_source() {
+        let cache_dir = std::env::temp_dir().join(format!("spirv-source-{}", std::process::id()));
+        let mut compiler = CompilerBuilder::new()
+            .with_macro("MY_MACRO", Some("1"))
+            .with_cache_dir(cache_dir.clone())
+            .build()
+            .unwrap();
+        let path = "test-spirv/test-macro.vert";
+
+        compiler
+            .compile_from_file(path, ShaderKind::Vertex, true)
+            .unwrap();
+        assert_eq!(compiler.last_compile_source(), CompileSource::Compiled);
+        compiler
+            .compile_from_file(path, ShaderKind::Vertex, true)
+            .unwrap();
+        assert_eq!(compiler.last_compile_source(), CompileSource::InMemoryCache);
+        compiler.clear_cache();
+        compiler
+            .compile_from_file(path, ShaderKind::Vertex, true)
+            .unwrap();
+        assert_eq!(compiler.last_compile_source(), CompileSource::DiskCache);
+        assert!(compiler.last_was_cache_hit());
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_would_recompile() {
+        let cache_dir = std::env::temp_dir().join(format!("spirv-dry-run-{}", std::process::id()));
+        let mut compiler = CompilerBuilder::new()
             .with_macro("MY_MACRO", Some("1"))
+            .with_cache_dir(cache_dir.clone())
             .build()
             .unwrap();
+        let path = "test-spirv/test-macro.vert";
+
+        assert!(compiler.would_recompile(path));
+        compiler
+            .compile_from_file(path, ShaderKind::Vertex, true)
+            .unwrap();
+        assert!(!compiler.would_recompile(path));
+        compiler.clear_cache();
+        assert!(!compiler.would_recompile(path));
 
+        // Different options invalidate the cache file
+        compiler.add_macro_definition("OTHER", None);
+        assert!(compiler.would_recompile(path));
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_extension() {
+        let dir = TempDir::new("cache-extension");
+        let mut compiler = CompilerBuilder::new()
+            .with_include_dir("test-spirv")
+            .with_cache_extension(".cache.spv")
+            .with_cache_dir(dir.0.clone())
+            .build()
+            .unwrap();
         let result =
-            compiler.compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false);
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, true);
         assert!(result.is_ok());
+
+        let files: Vec<_> = std::fs::read_dir(&dir.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].starts_with("test-include.vert."));
+        assert!(files[0].ends_with(".cache.spv"));
     }
 
     #[test]
-    fn test_without_macro() {
+    fn test_memory_cache() {
         let mut compiler = CompilerBuilder::new()
             .with_include_dir("test-spirv")
             .build()
             .unwrap();
 
         let result =
-            compiler.compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, false);
-        assert!(result.is_err());
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+        assert_eq!(compiler.cache_len(), 1);
+        assert!(compiler.remove_from_cache("test-spirv/test-include.vert"));
+        assert!(!compiler.remove_from_cache("test-spirv/test-include.vert"));
+        assert_eq!(compiler.cache_len(), 0);
+
+        let result =
+            compiler.compile_from_file("test-spirv/test-include.vert", ShaderKind::Vertex, false);
+        assert!(result.is_ok());
+        compiler.clear_cache();
+        assert_eq!(compiler.cache_len(), 0);
     }
 
     #[test]
-    fn test_cache() {
-        let cached = PathBuf::from("test-spirv/test-macro.vert.spv");
+    fn test_cache_fingerprint() {
+        let cached = PathBuf::from("test-spirv/test-fingerprint.vert.spv");
         if cached.exists() {
             std::fs::remove_file(&cached).unwrap();
         }
 
         let mut compiler = CompilerBuilder::new()
-            .with_include_dir("test-spirv")
             .with_macro("MY_MACRO", Some("1"))
             .build()
             .unwrap();
-
-        let result =
-            compiler.compile_from_file("test-spirv/test-macro.vert", ShaderKind::Vertex, true);
+        let result = compiler.compile_from_file(
+            "test-spirv/test-fingerprint.vert",
+            ShaderKind::Vertex,
+            true,
+        );
         assert!(result.is_ok());
         assert!(cached.exists());
+
+        // A different macro value must not be served the binary cached above
+        let mut compiler = CompilerBuilder::new()
+            .with_macro("MY_MACRO", Some("0"))
+            .build()
+            .unwrap();
+        let result = compiler.compile_from_file(
+            "test-spirv/test-fingerprint.vert",
+            ShaderKind::Vertex,
+            true,
+        );
+        assert!(result.is_err());
         // Cleanup
         std::fs::remove_file(cached).unwrap();
     }
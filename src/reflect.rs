@@ -0,0 +1,470 @@
+use crate::{spirv, CompilerError};
+use std::collections::HashMap;
+
+const OP_NAME: u32 = 5;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+
+/// Types nested deeper than this are treated as cyclic, which only malformed binaries are.
+const MAX_TYPE_DEPTH: u32 = 64;
+
+/// The kind of descriptor a resource binds to, named after `VkDescriptorType`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DescriptorKind {
+    Sampler,
+    CombinedImageSampler,
+    SampledImage,
+    StorageImage,
+    UniformTexelBuffer,
+    StorageTexelBuffer,
+    UniformBuffer,
+    StorageBuffer,
+    AccelerationStructure,
+}
+
+/// A resource bound through a descriptor set.
+///
+/// `count` is the number of descriptors of an array binding, 0 for runtime sized arrays.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindingInfo {
+    pub set: u32,
+    pub binding: u32,
+    pub kind: DescriptorKind,
+    pub count: u32,
+    pub name: Option<String>,
+}
+
+/// The bytes of a push constant block that are used by a shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PushConstantRange {
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// The resources used by a shader, see `reflect`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reflection {
+    pub bindings: Vec<BindingInfo>,
+    pub push_constants: Vec<PushConstantRange>,
+}
+
+impl Reflection {
+    /// Returns the bindings ordered by set, then binding, then name.
+    pub fn bindings_sorted(&self) -> Vec<BindingInfo> {
+        let mut bindings = self.bindings.clone();
+        bindings.sort_by(|a, b| (a.set, a.binding, &a.name).cmp(&(b.set, b.binding, &b.name)));
+        bindings
+    }
+
+    /// Returns the `(offset, size)` of the range covering every push constant range, or
+    /// `None` if the shader uses no push constants.
+    pub fn push_constant_range(&self) -> Option<(u32, u32)> {
+        let start = self.push_constants.iter().map(|range| range.offset).min()?;
+        let end = self
+            .push_constants
+            .iter()
+            .map(|range| range.offset + range.size)
+            .max()?;
+        Some((start, end - start))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Scalar(u32),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array(u32, Option<u32>),
+    RuntimeArray(u32),
+    Struct(Vec<u32>),
+    Pointer(u32),
+    AccelerationStructure,
+}
+
+#[derive(Debug, Default)]
+struct Module {
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+    variables: Vec<(u32, u32, u32)>,
+}
+
+impl Module {
+    fn parse(binary: &[u32]) -> Module {
+        let mut module = Module::default();
+        for (opcode, operands) in spirv::instructions(binary) {
+            let operand = |index: usize| operands.get(index).copied().unwrap_or(0);
+            let ty = match opcode {
+                OP_NAME => {
+                    let name = spirv::literal_string(&operands[1.min(operands.len())..]);
+                    module.names.insert(operand(0), name);
+                    None
+                }
+                OP_TYPE_INT | OP_TYPE_FLOAT => Some(Type::Scalar(operand(1) / 8)),
+                OP_TYPE_VECTOR => Some(Type::Vector(operand(1), operand(2))),
+                OP_TYPE_MATRIX => Some(Type::Matrix(operand(1), operand(2))),
+                OP_TYPE_IMAGE => Some(Type::Image {
+                    dim: operand(2),
+                    sampled: operand(6),
+                }),
+                OP_TYPE_SAMPLER => Some(Type::Sampler),
+                OP_TYPE_SAMPLED_IMAGE => Some(Type::SampledImage),
+                OP_TYPE_ARRAY => Some(Type::Array(
+                    operand(1),
+                    module.constants.get(&operand(2)).copied(),
+                )),
+                OP_TYPE_RUNTIME_ARRAY => Some(Type::RuntimeArray(operand(1))),
+                OP_TYPE_STRUCT => Some(Type::Struct(operands[1.min(operands.len())..].to_vec())),
+                OP_TYPE_POINTER => Some(Type::Pointer(operand(2))),
+                OP_TYPE_ACCELERATION_STRUCTURE => Some(Type::AccelerationStructure),
+                OP_CONSTANT => {
+                    module.constants.insert(operand(1), operand(2));
+                    None
+                }
+                OP_VARIABLE => {
+                    module.variables.push((operand(0), operand(1), operand(2)));
+                    None
+                }
+                OP_DECORATE => {
+                    module
+                        .decorations
+                        .insert((operand(0), operand(1)), operand(2));
+                    None
+                }
+                OP_MEMBER_DECORATE => {
+                    module
+                        .member_decorations
+                        .insert((operand(0), operand(1), operand(2)), operand(3));
+                    None
+                }
+                _ => None,
+            };
+
+            if let Some(ty) = ty {
+                module.types.insert(operand(0), ty);
+            }
+        }
+        module
+    }
+
+    /// Strips arrays off of `id`, returning the element type and the descriptor count.
+    fn element_type(&self, mut id: u32) -> (u32, u32) {
+        let mut count: u32 = 1;
+        for _ in 0..MAX_TYPE_DEPTH {
+            match self.types.get(&id) {
+                Some(Type::Array(element, length)) => {
+                    count = count.saturating_mul(length.unwrap_or(1));
+                    id = *element;
+                }
+                Some(Type::RuntimeArray(element)) => {
+                    count = 0;
+                    id = *element;
+                }
+                _ => break,
+            }
+        }
+        (id, count)
+    }
+
+    fn descriptor_kind(&self, id: u32, storage_class: u32) -> Option<DescriptorKind> {
+        let is_block = |decoration| self.decorations.contains_key(&(id, decoration));
+        match (storage_class, self.types.get(&id)?) {
+            (STORAGE_CLASS_STORAGE_BUFFER, _) => Some(DescriptorKind::StorageBuffer),
+            (STORAGE_CLASS_UNIFORM, _) if is_block(DECORATION_BUFFER_BLOCK) => {
+                Some(DescriptorKind::StorageBuffer)
+            }
+            (STORAGE_CLASS_UNIFORM, _) if is_block(DECORATION_BLOCK) => {
+                Some(DescriptorKind::UniformBuffer)
+            }
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Type::Sampler) => Some(DescriptorKind::Sampler),
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Type::SampledImage) => {
+                Some(DescriptorKind::CombinedImageSampler)
+            }
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Type::Image { dim, sampled }) => {
+                Some(match (*dim == DIM_BUFFER, *sampled == 2) {
+                    (false, false) => DescriptorKind::SampledImage,
+                    (false, true) => DescriptorKind::StorageImage,
+                    (true, false) => DescriptorKind::UniformTexelBuffer,
+                    (true, true) => DescriptorKind::StorageTexelBuffer,
+                })
+            }
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Type::AccelerationStructure) => {
+                Some(DescriptorKind::AccelerationStructure)
+            }
+            _ => None,
+        }
+    }
+
+    /// The size of `id` in bytes, `matrix_stride` is the stride of the enclosing member.
+    /// Sizes that don't fit saturate at `u32::MAX`, types nested too deep have a size of 0.
+    fn size_of(&self, id: u32, matrix_stride: Option<u32>, depth: u32) -> u32 {
+        if depth >= MAX_TYPE_DEPTH {
+            return 0;
+        }
+        let depth = depth + 1;
+        match self.types.get(&id) {
+            Some(Type::Scalar(size)) => *size,
+            Some(Type::Vector(component, count)) => {
+                self.size_of(*component, None, depth).saturating_mul(*count)
+            }
+            Some(Type::Matrix(column, count)) => {
+                let column_size = self.size_of(*column, None, depth);
+                matrix_stride.unwrap_or(column_size).saturating_mul(*count)
+            }
+            Some(Type::Array(element, length)) => {
+                let stride = self
+                    .decorations
+                    .get(&(id, DECORATION_ARRAY_STRIDE))
+                    .copied()
+                    .unwrap_or_else(|| self.size_of(*element, matrix_stride, depth));
+                stride.saturating_mul(length.unwrap_or(0))
+            }
+            Some(Type::Struct(members)) => members
+                .iter()
+                .enumerate()
+                .map(|(index, member)| {
+                    let index = index as u32;
+                    let offset = self
+                        .member_decorations
+                        .get(&(id, index, DECORATION_OFFSET))
+                        .copied()
+                        .unwrap_or(0);
+                    let stride = self
+                        .member_decorations
+                        .get(&(id, index, DECORATION_MATRIX_STRIDE))
+                        .copied();
+                    offset.saturating_add(self.size_of(*member, stride, depth))
+                })
+                .max()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    fn push_constant_range(&self, id: u32) -> Option<PushConstantRange> {
+        let members = match self.types.get(&id) {
+            Some(Type::Struct(members)) => members.len() as u32,
+            _ => return None,
+        };
+        let offset = (0..members)
+            .filter_map(|index| {
+                self.member_decorations
+                    .get(&(id, index, DECORATION_OFFSET))
+                    .copied()
+            })
+            .min()
+            .unwrap_or(0);
+        Some(PushConstantRange {
+            offset,
+            size: self.size_of(id, None, 0).saturating_sub(offset),
+        })
+    }
+}
+
+/// Finds the descriptor bindings and push constant blocks used by a compiled shader.
+pub fn reflect(binary: &[u32]) -> Result<Reflection, CompilerError> {
+    spirv::validate(binary).map_err(CompilerError::Validation)?;
+    let module = Module::parse(binary);
+
+    let mut reflection = Reflection::default();
+    for (pointer, id, storage_class) in module.variables.iter().copied() {
+        let pointee = match module.types.get(&pointer) {
+            Some(Type::Pointer(pointee)) => *pointee,
+            _ => continue,
+        };
+        let (element, count) = module.element_type(pointee);
+
+        if storage_class == STORAGE_CLASS_PUSH_CONSTANT {
+            reflection
+                .push_constants
+                .extend(module.push_constant_range(element));
+            continue;
+        }
+
+        let set = module.decorations.get(&(id, DECORATION_DESCRIPTOR_SET));
+        let binding = module.decorations.get(&(id, DECORATION_BINDING));
+        let kind = module.descriptor_kind(element, storage_class);
+        if let (Some(set), Some(binding), Some(kind)) = (set, binding, kind) {
+            reflection.bindings.push(BindingInfo {
+                set: *set,
+                binding: *binding,
+                kind,
+                count,
+                name: module
+                    .names
+                    .get(&id)
+                    .filter(|name| !name.is_empty())
+                    .cloned(),
+            });
+        }
+    }
+    Ok(reflection)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reflect::*;
+
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    #[test]
+    fn test_reflect() {
+        let mut binary = vec![spirv::MAGIC, 0x0001_0000, 0, 9, 0];
+        for (opcode, operands) in [
+            (OP_NAME, &[5, 0x0062_7575][..]),
+            (OP_DECORATE, &[3, DECORATION_BLOCK]),
+            (OP_DECORATE, &[5, DECORATION_DESCRIPTOR_SET, 0]),
+            (OP_DECORATE, &[5, DECORATION_BINDING, 1]),
+            (OP_MEMBER_DECORATE, &[3, 0, DECORATION_OFFSET, 0]),
+            (OP_DECORATE, &[6, DECORATION_BLOCK]),
+            (OP_MEMBER_DECORATE, &[6, 0, DECORATION_OFFSET, 16]),
+            (OP_TYPE_FLOAT, &[1, 32]),
+            (OP_TYPE_VECTOR, &[2, 1, 4]),
+            (OP_TYPE_STRUCT, &[3, 2]),
+            (OP_TYPE_POINTER, &[4, STORAGE_CLASS_UNIFORM, 3]),
+            (OP_VARIABLE, &[4, 5, STORAGE_CLASS_UNIFORM]),
+            (OP_TYPE_STRUCT, &[6, 1]),
+            (OP_TYPE_POINTER, &[7, STORAGE_CLASS_PUSH_CONSTANT, 6]),
+            (OP_VARIABLE, &[7, 8, STORAGE_CLASS_PUSH_CONSTANT]),
+        ] {
+            binary.extend(instruction(opcode, operands));
+        }
+
+        assert_eq!(
+            reflect(&binary).unwrap(),
+            Reflection {
+                bindings: vec![BindingInfo {
+                    set: 0,
+                    binding: 1,
+                    kind: DescriptorKind::UniformBuffer,
+                    count: 1,
+                    name: Some(String::from("uub")),
+                }],
+                push_constants: vec![PushConstantRange {
+                    offset: 16,
+                    size: 4
+                }],
+            }
+        );
+    }
+
+    fn module(instructions: &[(u32, &[u32])]) -> Vec<u32> {
+        let mut binary = vec![spirv::MAGIC, 0x0001_0000, 0, 16, 0];
+        for (opcode, operands) in instructions {
+            binary.extend(instruction(*opcode, operands));
+        }
+        binary
+    }
+
+    #[test]
+    fn test_reflect_cyclic_type() {
+        // An array of itself and a struct containing itself, neither of which is valid SPIR-V
+        let binary = module(&[
+            (OP_DECORATE, &[5, DECORATION_DESCRIPTOR_SET, 0]),
+            (OP_DECORATE, &[5, DECORATION_BINDING, 0]),
+            (OP_TYPE_INT, &[1, 32, 0]),
+            (OP_CONSTANT, &[1, 2, 4]),
+            (OP_TYPE_ARRAY, &[3, 3, 2]),
+            (OP_TYPE_POINTER, &[4, STORAGE_CLASS_UNIFORM_CONSTANT, 3]),
+            (OP_VARIABLE, &[4, 5, STORAGE_CLASS_UNIFORM_CONSTANT]),
+            (OP_TYPE_STRUCT, &[6, 6]),
+            (OP_TYPE_POINTER, &[7, STORAGE_CLASS_PUSH_CONSTANT, 6]),
+            (OP_VARIABLE, &[7, 8, STORAGE_CLASS_PUSH_CONSTANT]),
+        ]);
+
+        let reflection = reflect(&binary).unwrap();
+        assert!(reflection.bindings.is_empty());
+        assert_eq!(
+            reflection.push_constants,
+            vec![PushConstantRange { offset: 0, size: 0 }]
+        );
+    }
+
+    #[test]
+    fn test_reflect_oversized_array() {
+        // 2^28 vec4s take 2^32 bytes, which doesn't fit in a u32
+        let binary = module(&[
+            (OP_TYPE_FLOAT, &[1, 32]),
+            (OP_TYPE_VECTOR, &[2, 1, 4]),
+            (OP_CONSTANT, &[1, 3, 0x1000_0000]),
+            (OP_TYPE_ARRAY, &[4, 2, 3]),
+            (OP_TYPE_STRUCT, &[5, 4]),
+            (OP_TYPE_POINTER, &[6, STORAGE_CLASS_PUSH_CONSTANT, 5]),
+            (OP_VARIABLE, &[6, 7, STORAGE_CLASS_PUSH_CONSTANT]),
+        ]);
+
+        assert_eq!(
+            reflect(&binary).unwrap().push_constants,
+            vec![PushConstantRange {
+                offset: 0,
+                size: u32::MAX
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sorted() {
+        let binding = |set, binding| BindingInfo {
+            set,
+            binding,
+            kind: DescriptorKind::StorageBuffer,
+            count: 1,
+            name: None,
+        };
+        let reflection = Reflection {
+            bindings: vec![binding(1, 0), binding(0, 2), binding(0, 1)],
+            push_constants: vec![
+                PushConstantRange {
+                    offset: 16,
+                    size: 8,
+                },
+                PushConstantRange { offset: 0, size: 4 },
+            ],
+        };
+
+        assert_eq!(
+            reflection.bindings_sorted(),
+            vec![binding(0, 1), binding(0, 2), binding(1, 0)]
+        );
+        assert_eq!(reflection.push_constant_range(), Some((0, 24)));
+        assert_eq!(Reflection::default().push_constant_range(), None);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{CompilerBuilder, CompilerError, OptimizationLevel, SpirvVersion, TargetEnv};
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// Target environments by the name used in settings and on the command line.
+pub(crate) const TARGET_ENVS: &[(&str, TargetEnv, shaderc::EnvVersion)] = &[
+    (
+        "vulkan1.0",
+        TargetEnv::Vulkan,
+        shaderc::EnvVersion::Vulkan1_0,
+    ),
+    (
+        "vulkan1.1",
+        TargetEnv::Vulkan,
+        shaderc::EnvVersion::Vulkan1_1,
+    ),
+    (
+        "vulkan1.2",
+        TargetEnv::Vulkan,
+        shaderc::EnvVersion::Vulkan1_2,
+    ),
+    (
+        "opengl4.5",
+        TargetEnv::OpenGL,
+        shaderc::EnvVersion::OpenGL4_5,
+    ),
+];
+
+const SPIRV_VERSIONS: &[(&str, SpirvVersion)] = &[
+    ("1.0", SpirvVersion::V1_0),
+    ("1.1", SpirvVersion::V1_1),
+    ("1.2", SpirvVersion::V1_2),
+    ("1.3", SpirvVersion::V1_3),
+    ("1.4", SpirvVersion::V1_4),
+    ("1.5", SpirvVersion::V1_5),
+    ("1.6", SpirvVersion::V1_6),
+];
+
+const OPT_LEVELS: &[(&str, OptimizationLevel)] = &[
+    ("zero", OptimizationLevel::Zero),
+    ("size", OptimizationLevel::Size),
+    ("performance", OptimizationLevel::Performance),
+];
+
+/// Compile options as plain data, for storing shader configuration declaratively, such as
+/// in a TOML file. With the `serde` feature it can be deserialized, missing fields keep their
+/// defaults. See `CompilerBuilder::from_settings`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CompileSettings {
+    /// One of `vulkan1.0`, `vulkan1.1`, `vulkan1.2` or `opengl4.5`.
+    pub target_env: Option<String>,
+    /// `1.0` up to `1.6`.
+    pub spirv_version: Option<String>,
+    /// One of `zero`, `size` or `performance`.
+    pub opt_level: Option<String>,
+    /// Macro definitions, an empty value defines the macro without a value.
+    pub macros: BTreeMap<String, String>,
+    pub include_dirs: Vec<PathBuf>,
+    pub warnings_as_errors: bool,
+}
+
+fn lookup<T: Copy>(table: &[(&str, T)], what: &str, name: &str) -> Result<T, CompilerError> {
+    table
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| *value)
+        .ok_or_else(|| CompilerError::InitError(format!("unknown {}: {}", what, name)))
+}
+
+impl<'a> CompilerBuilder<'a> {
+    /// Creates a builder configured with `settings`, returns `CompilerError::InitError` if one
+    /// of its values is not recognized or a macro contains a NUL character.
+    pub fn from_settings(settings: &CompileSettings) -> Result<Self, CompilerError> {
+        let mut builder = CompilerBuilder::new();
+        if let Some(name) = settings.target_env.as_ref() {
+            let (_, env, version) = TARGET_ENVS
+                .iter()
+                .find(|(env, _, _)| env == name)
+                .ok_or_else(|| {
+                    CompilerError::InitError(format!("unknown target environment: {}", name))
+                })?;
+            builder = builder.with_target_env(*env, *version as u32);
+        }
+        if let Some(name) = settings.spirv_version.as_ref() {
+            builder = builder.with_target_spirv(lookup(SPIRV_VERSIONS, "SPIR-V version", name)?);
+        }
+        if let Some(name) = settings.opt_level.as_ref() {
+            builder = builder.with_opt_level(lookup(OPT_LEVELS, "optimization level", name)?);
+        }
+        for (name, value) in settings.macros.iter() {
+            // shaderc panics on macros with a NUL in them instead of reporting an error
+            if name.contains('\0') || value.contains('\0') {
+                return Err(CompilerError::InitError(format!(
+                    "macro {:?} contains a NUL character",
+                    name
+                )));
+            }
+            builder = builder.with_macro(name, Some(value.as_str()).filter(|v| !v.is_empty()));
+        }
+        #[cfg(feature = "fs")]
+        {
+            builder = builder.with_include_dirs(settings.include_dirs.iter());
+        }
+        if settings.warnings_as_errors {
+            builder = builder.with_warnings_as_errors();
+        }
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{settings::*, CompileSetting};
+
+    #[test]
+    fn test_from_settings() {
+        let mut settings = CompileSettings {
+            target_env: Some(String::from("vulkan1.2")),
+            spirv_version: Some(String::from("1.5")),
+            opt_level: Some(String::from("size")),
+            include_dirs: vec![PathBuf::from("test-spirv")],
+            warnings_as_errors: true,
+            ..CompileSettings::default()
+        };
+        settings
+            .macros
+            .insert(String::from("MY_MACRO"), String::from("1"));
+        settings.macros.insert(String::from("EMPTY"), String::new());
+
+        let builder = CompilerBuilder::from_settings(&settings).unwrap();
+        assert_eq!(
+            builder.settings,
+            vec![
+                CompileSetting::TargetEnv(TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_2 as u32),
+                CompileSetting::TargetSpirv(SpirvVersion::V1_5),
+                CompileSetting::OptimizationLevel(OptimizationLevel::Size),
+                CompileSetting::Macro(String::from("EMPTY"), None),
+                CompileSetting::Macro(String::from("MY_MACRO"), Some(String::from("1"))),
+                CompileSetting::WarningsAsErrors,
+            ]
+        );
+
+        settings.opt_level = Some(String::from("fast"));
+        assert!(matches!(
+            CompilerBuilder::from_settings(&settings),
+            Err(CompilerError::InitError(_))
+        ));
+
+        for (name, value) in [("NUL\0", "1"), ("VALUE", "1\0")].iter() {
+            let mut settings = CompileSettings::default();
+            settings
+                .macros
+                .insert(String::from(*name), String::from(*value));
+            assert!(matches!(
+                CompilerBuilder::from_settings(&settings),
+                Err(CompilerError::InitError(_))
+            ));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_settings() {
+        let settings: CompileSettings = toml::from_str(
+            "target_env = \"vulkan1.1\"\n\
+             include_dirs = [\"shaders\"]\n\
+             [macros]\n\
+             MY_MACRO = \"1\"\n",
+        )
+        .unwrap();
+
+        let mut expected = CompileSettings {
+            target_env: Some(String::from("vulkan1.1")),
+            include_dirs: vec![PathBuf::from("shaders")],
+            ..CompileSettings::default()
+        };
+        expected
+            .macros
+            .insert(String::from("MY_MACRO"), String::from("1"));
+        assert_eq!(settings, expected);
+        assert_eq!(settings.spirv_version, None);
+        assert!(!settings.warnings_as_errors);
+    }
+}
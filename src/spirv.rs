@@ -0,0 +1,260 @@
+//! Helpers for inspecting SPIR-V binaries.
+
+use crate::CompilerError;
+
+pub(crate) const MAGIC: u32 = 0x0723_0203;
+pub(crate) const HEADER_LEN: usize = 5;
+
+const OP_SOURCE_CONTINUED: u32 = 2;
+const OP_SOURCE: u32 = 3;
+const OP_SOURCE_EXTENSION: u32 = 4;
+const OP_NAME: u32 = 5;
+const OP_MEMBER_NAME: u32 = 6;
+const OP_STRING: u32 = 7;
+const OP_LINE: u32 = 8;
+const OP_NO_LINE: u32 = 317;
+const OP_MODULE_PROCESSED: u32 = 330;
+
+const DEBUG_OPCODES: &[u32] = &[
+    OP_SOURCE_CONTINUED,
+    OP_SOURCE,
+    OP_SOURCE_EXTENSION,
+    OP_NAME,
+    OP_MEMBER_NAME,
+    OP_LINE,
+    OP_NO_LINE,
+    OP_MODULE_PROCESSED,
+];
+
+/// Checks that `words` is a well formed SPIR-V module: a valid header followed by
+/// instructions that exactly fill the binary.
+///
+/// This is a structural check only, the semantics of instructions are not validated.
+pub(crate) fn validate(words: &[u32]) -> Result<(), String> {
+    if words.len() < HEADER_LEN {
+        return Err(format!(
+            "binary has {} words, which is less than the {} word header",
+            words.len(),
+            HEADER_LEN
+        ));
+    }
+    if words[0] != MAGIC {
+        return Err(format!("invalid magic number {:#010x}", words[0]));
+    }
+
+    let (major, minor) = ((words[1] >> 16) & 0xff, (words[1] >> 8) & 0xff);
+    if major != 1 || minor > 6 {
+        return Err(format!("unsupported SPIR-V version {}.{}", major, minor));
+    }
+    if words[3] == 0 {
+        return Err(String::from("id bound is 0"));
+    }
+
+    let mut offset = HEADER_LEN;
+    while offset < words.len() {
+        let word_count = (words[offset] >> 16) as usize;
+        if word_count == 0 {
+            return Err(format!(
+                "instruction at word {} has a word count of 0",
+                offset
+            ));
+        }
+        if offset + word_count > words.len() {
+            return Err(format!(
+                "instruction at word {} with {} words runs past the end of the binary",
+                offset, word_count
+            ));
+        }
+        offset += word_count;
+    }
+    Ok(())
+}
+
+/// Returns the `(major, minor)` SPIR-V version a binary declares in its header, or `None`
+/// when `binary` doesn't start with a SPIR-V header.
+pub fn spirv_version_of(binary: &[u32]) -> Option<(u8, u8)> {
+    if binary.len() < HEADER_LEN || binary[0] != MAGIC {
+        return None;
+    }
+    Some(((binary[1] >> 16) as u8, (binary[1] >> 8) as u8))
+}
+
+/// Iterates over the opcode and operands of every instruction in a binary that passed
+/// `validate`.
+pub(crate) fn instructions(words: &[u32]) -> impl Iterator<Item = (u32, &[u32])> {
+    let mut rest = &words[HEADER_LEN.min(words.len())..];
+    std::iter::from_fn(move || {
+        let word_count = ((*rest.first()? >> 16) as usize).clamp(1, rest.len());
+        let (instruction, remainder) = rest.split_at(word_count);
+        rest = remainder;
+        Some((instruction[0] & 0xffff, &instruction[1..]))
+    })
+}
+
+/// Removes source, name and line debug instructions from `binary`.
+///
+/// `OpString`s are removed as well, unless a remaining instruction may refer to them, such
+/// as a `debugPrintfEXT` format string.
+pub fn strip_debug(binary: &[u32]) -> Result<Vec<u32>, CompilerError> {
+    validate(binary).map_err(CompilerError::Validation)?;
+
+    let kept: Vec<(u32, &[u32])> = instructions(binary)
+        .filter(|(opcode, _)| !DEBUG_OPCODES.contains(opcode))
+        .collect();
+    // Operand types aren't known here, so any operand word equal to a string's id keeps it
+    let referenced = |id: u32| {
+        kept.iter()
+            .any(|(opcode, operands)| *opcode != OP_STRING && operands.contains(&id))
+    };
+
+    Ok(assemble(
+        &binary[..HEADER_LEN],
+        kept.iter().copied().filter(|(opcode, operands)| {
+            *opcode != OP_STRING || operands.first().is_some_and(|id| referenced(*id))
+        }),
+    ))
+}
+
+/// Removes the source text embedded in `OpSource`, keeping the file and line debug
+/// information. `binary` must be a well formed module.
+pub(crate) fn strip_source(binary: &[u32]) -> Vec<u32> {
+    assemble(
+        &binary[..HEADER_LEN.min(binary.len())],
+        instructions(binary)
+            .filter(|(opcode, _)| *opcode != OP_SOURCE_CONTINUED)
+            .map(|(opcode, operands)| match opcode {
+                // Source language, version and file, followed by the optional source text
+                OP_SOURCE => (opcode, &operands[..operands.len().min(3)]),
+                _ => (opcode, operands),
+            }),
+    )
+}
+
+/// Encodes `instructions` into a binary with `header`.
+fn assemble<'a>(header: &[u32], instructions: impl Iterator<Item = (u32, &'a [u32])>) -> Vec<u32> {
+    let mut words = header.to_vec();
+    for (opcode, operands) in instructions {
+        words.push(((operands.len() as u32 + 1) << 16) | opcode);
+        words.extend_from_slice(operands);
+    }
+    words
+}
+
+/// Decodes a nul-terminated literal string operand.
+pub(crate) fn literal_string(operands: &[u32]) -> String {
+    let bytes: Vec<u8> = operands
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|byte| *byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spirv::*;
+
+    #[test]
+    fn test_validate() {
+        // OpCapability Shader, OpMemoryModel Logical GLSL450
+        let mut words = vec![
+            MAGIC,
+            0x0001_0000,
+            0,
+            1,
+            0,
+            0x0002_0011,
+            1,
+            0x0003_000e,
+            0,
+            1,
+        ];
+        assert!(validate(&words).is_ok());
+
+        words.pop();
+        assert!(validate(&words).is_err());
+
+        words[0] = MAGIC.swap_bytes();
+        assert!(validate(&words).is_err());
+        assert!(validate(&[MAGIC]).is_err());
+    }
+
+    #[test]
+    fn test_spirv_version_of() {
+        assert_eq!(
+            spirv_version_of(&[MAGIC, 0x0001_0300, 0, 1, 0]),
+            Some((1, 3))
+        );
+        assert_eq!(spirv_version_of(&[MAGIC, 0x0001_0300]), None);
+        assert_eq!(spirv_version_of(&[0, 0x0001_0300, 0, 1, 0]), None);
+    }
+
+    #[test]
+    fn test_instructions() {
+        let words = vec![
+            MAGIC,
+            0x0001_0000,
+            0,
+            1,
+            0,
+            0x0002_0011,
+            1,
+            0x0003_000e,
+            0,
+            1,
+        ];
+        let instructions: Vec<_> = instructions(&words).collect();
+        assert_eq!(instructions, vec![(17, &[1][..]), (14, &[0, 1][..])]);
+
+        // "main" followed by its nul terminator
+        assert_eq!(literal_string(&[0x6e69_616d, 0]), "main");
+    }
+
+    #[test]
+    fn test_strip_debug() {
+        // OpCapability Shader, OpString %3 "main", OpSource GLSL 450 %3, OpName %2 "main",
+        // OpMemoryModel Logical GLSL450
+        let words = vec![
+            MAGIC,
+            0x0001_0000,
+            0,
+            4,
+            0,
+            0x0002_0011,
+            1,
+            0x0004_0007,
+            3,
+            0x6e69_616d,
+            0,
+            0x0004_0003,
+            2,
+            450,
+            3,
+            0x0004_0005,
+            2,
+            0x6e69_616d,
+            0,
+            0x0003_000e,
+            0,
+            1,
+        ];
+        let stripped = strip_debug(&words).unwrap();
+        assert_eq!(stripped, [&words[..7], &words[19..]].concat());
+        assert!(strip_debug(&words[..4]).is_err());
+
+        // An OpString without operands is malformed, but structurally valid
+        let mut empty_string = words[..5].to_vec();
+        empty_string.extend([0x0001_0007, 0x0003_000e, 0, 1]);
+        assert_eq!(
+            strip_debug(&empty_string).unwrap(),
+            [&words[..5], &words[19..]].concat()
+        );
+
+        // OpSource GLSL 450 %3 "main" keeps its file but loses its text
+        let mut source = words.clone();
+        source.splice(11..15, [0x0005_0003, 2, 450, 3, 0x6e69_616d]);
+        let stripped = strip_source(&source);
+        assert_eq!(&stripped[11..15], &[0x0004_0003, 2, 450, 3]);
+        assert_eq!(stripped.len(), source.len() - 1);
+    }
+}
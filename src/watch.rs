@@ -0,0 +1,205 @@
+//! Recompiles shaders whenever their source or one of their includes changes.
+//!
+//! Changes are reported by the platform's file notification API through `notify`. The
+//! directories of a shader and its includes are watched rather than the files themselves,
+//! so editors that save by replacing a file are picked up as well.
+
+use crate::{Compiler, CompilerError, ShaderKind};
+use notify::{event::EventKind, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+    },
+    time::Duration,
+};
+
+/// How long `run` waits for a change before checking its stop flag again.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct WatchedShader {
+    path: PathBuf,
+    kind: ShaderKind,
+    /// The canonical paths of the source and all of its resolved includes.
+    dependencies: Vec<PathBuf>,
+}
+
+/// Watches a set of shaders and recompiles them with its compiler when they change.
+#[derive(Debug)]
+pub struct ShaderWatcher<'a> {
+    compiler: Compiler<'a>,
+    shaders: Vec<WatchedShader>,
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    watched_dirs: Vec<PathBuf>,
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl<'a> ShaderWatcher<'a> {
+    /// Creates a watcher, fails with `CompilerError::InitError` if file notifications are not
+    /// available.
+    pub fn new(compiler: Compiler<'a>) -> Result<Self, CompilerError> {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender).map_err(|e| {
+            CompilerError::InitError(format!("could not watch for file changes: {}", e))
+        })?;
+        Ok(Self {
+            compiler,
+            shaders: Vec::new(),
+            watcher,
+            events,
+            watched_dirs: Vec::new(),
+        })
+    }
+
+    /// Starts watching `path` and returns the result of compiling it once.
+    pub fn watch<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+        kind: ShaderKind,
+    ) -> Result<Vec<u32>, CompilerError> {
+        let mut shader = WatchedShader {
+            path: path.as_ref().to_path_buf(),
+            kind,
+            dependencies: Vec::new(),
+        };
+        let result = self.recompile(&mut shader);
+        self.shaders.push(shader);
+        result
+    }
+
+    /// Recompiles every watched shader of which a dependency changed since it was last
+    /// compiled and passes the results to `callback`. Doesn't wait for changes.
+    pub fn poll<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&Path, Result<Vec<u32>, CompilerError>),
+    {
+        let events: Vec<_> = self.events.try_iter().collect();
+        self.handle(events, &mut callback);
+    }
+
+    /// Waits for changes and handles them like `poll`, until `stop` is set.
+    pub fn run<F>(&mut self, stop: &AtomicBool, mut callback: F)
+    where
+        F: FnMut(&Path, Result<Vec<u32>, CompilerError>),
+    {
+        while !stop.load(Ordering::Relaxed) {
+            match self.events.recv_timeout(STOP_CHECK_INTERVAL) {
+                Ok(event) => {
+                    let mut events = vec![event];
+                    events.extend(self.events.try_iter());
+                    self.handle(events, &mut callback);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    fn handle<F>(&mut self, events: Vec<notify::Result<Event>>, callback: &mut F)
+    where
+        F: FnMut(&Path, Result<Vec<u32>, CompilerError>),
+    {
+        // Compiling reads the sources, which must not count as a change
+        let changed: Vec<PathBuf> = events
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|event| !matches!(event.kind, EventKind::Access(_)))
+            .flat_map(|event| event.paths)
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+
+        let mut shaders = std::mem::take(&mut self.shaders);
+        for shader in shaders.iter_mut() {
+            if shader
+                .dependencies
+                .iter()
+                .any(|dependency| changed.contains(dependency))
+            {
+                let result = self.recompile(shader);
+                callback(shader.path.as_path(), result);
+            }
+        }
+        self.shaders = shaders;
+    }
+
+    fn recompile(&mut self, shader: &mut WatchedShader) -> Result<Vec<u32>, CompilerError> {
+        // The in-memory cache has no notion of includes, drop the stale binary
+        self.compiler.remove_from_cache(&shader.path);
+        let result = self
+            .compiler
+            .compile_from_file(&shader.path, shader.kind, false);
+
+        shader.dependencies = vec![canonical(&shader.path)];
+        shader.dependencies.extend(self.compiler.last_includes());
+        for dependency in shader.dependencies.iter() {
+            let dir = dependency.parent().unwrap_or_else(|| Path::new("."));
+            // A directory that can't be watched just doesn't trigger recompiles
+            if !self.watched_dirs.iter().any(|watched| watched == dir)
+                && self.watcher.watch(dir, RecursiveMode::NonRecursive).is_ok()
+            {
+                self.watched_dirs.push(dir.to_path_buf());
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::watch::*;
+    use crate::CompilerBuilder;
+    use std::time::Instant;
+
+    #[test]
+    fn test_watch_include() {
+        let dir = std::env::temp_dir().join(format!("spirv-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy(
+            "test-spirv/test-include.vert",
+            dir.join("test-include.vert"),
+        )
+        .unwrap();
+        std::fs::copy("test-spirv/structs.glsl", dir.join("structs.glsl")).unwrap();
+
+        let compiler = CompilerBuilder::new()
+            .with_include_dir(&dir)
+            .build()
+            .unwrap();
+        let mut watcher = ShaderWatcher::new(compiler).unwrap();
+        assert!(watcher
+            .watch(dir.join("test-include.vert"), ShaderKind::Vertex)
+            .is_ok());
+
+        let mut compiled = Vec::new();
+        watcher.poll(|path, result| compiled.push((path.to_path_buf(), result.is_ok())));
+        assert!(compiled.is_empty());
+
+        // Changing an include recompiles the shader that includes it
+        let structs = std::fs::read_to_string(dir.join("structs.glsl")).unwrap();
+        std::fs::write(dir.join("structs.glsl"), structs + "\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while compiled.is_empty() && Instant::now() < deadline {
+            watcher.poll(|path, result| compiled.push((path.to_path_buf(), result.is_ok())));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(compiled[0], (dir.join("test-include.vert"), true));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_stops() {
+        let compiler = CompilerBuilder::new().build().unwrap();
+        let mut watcher = ShaderWatcher::new(compiler).unwrap();
+        let stop = AtomicBool::new(true);
+        watcher.run(&stop, |_, _| panic!("nothing is watched"));
+    }
+}
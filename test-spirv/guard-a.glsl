@@ -0,0 +1,4 @@
+#ifndef GUARD_A
+#define GUARD_A
+#include "guard-b.glsl"
+#endif
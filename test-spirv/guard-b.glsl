@@ -0,0 +1,4 @@
+#ifndef GUARD_B
+#define GUARD_B
+#include "guard-a.glsl"
+#endif
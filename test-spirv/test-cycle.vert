@@ -0,0 +1,5 @@
+#version 450
+
+#include "cycle-a.glsl"
+
+void main() {}
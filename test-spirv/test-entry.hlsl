@@ -0,0 +1,4 @@
+float4 VSMain(float4 position : POSITION) : SV_POSITION
+{
+    return position;
+}
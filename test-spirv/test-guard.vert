@@ -0,0 +1,5 @@
+#version 450
+
+#include "guard-a.glsl"
+
+void main() {}
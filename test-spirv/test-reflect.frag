@@ -0,0 +1,19 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view;
+} camera;
+layout(set = 0, binding = 1) uniform sampler2D textures[4];
+layout(set = 1, binding = 0) buffer Lights {
+    vec4 lights[];
+};
+layout(push_constant) uniform Constants {
+    vec4 color;
+    uint index;
+} constants;
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    color = camera.view * texture(textures[constants.index], vec2(0.0)) * lights[0] * constants.color;
+}